@@ -2,12 +2,34 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use super::to_cstring;
+use crate::fs_context::explain_mount_failure;
+use crate::probe;
 use crate::{
-    io, libc, CString, FilesystemType, Mount, MountFlags, OsStrExt, Path, SupportedFilesystems,
-    Unmount, UnmountDrop, UnmountFlags,
+    io, libc, CString, FilesystemType, FsContext, Mount, MountFlags, OsStrExt, Path, SourceTag,
+    SupportedFilesystems, Unmount, UnmountDrop, UnmountFlags,
 };
-use libc::mount;
-use std::ptr;
+use libc::{mount, syscall, SYS_fsopen, ENODEV, ENOSYS, EPERM};
+use std::{
+    ffi::OsStr,
+    fs::{self, File},
+    os::unix::fs::FileTypeExt,
+    ptr,
+    sync::OnceLock,
+};
+
+/// Selects which kernel interface [`MountBuilder::mount`] mounts with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum MountApi {
+    /// Use the file descriptor based API when the kernel supports it, and the legacy
+    /// `mount(2)` system call otherwise.
+    #[default]
+    Auto,
+    /// Always use `fsopen`, `fsconfig`, `fsmount`, and `move_mount`, except for bind, move,
+    /// and remount operations, which have no equivalent and so always use `mount(2)`.
+    FsContext,
+    /// Always use the legacy `mount(2)` system call.
+    Legacy,
+}
 
 /// Builder API for mounting devices
 ///
@@ -33,6 +55,7 @@ pub struct MountBuilder<'a> {
     #[cfg(feature = "loop")]
     explicit_loopback: bool,
     data: Option<&'a str>,
+    api: MountApi,
 }
 
 impl<'a> MountBuilder<'a> {
@@ -57,6 +80,13 @@ impl<'a> MountBuilder<'a> {
         self
     }
 
+    /// Forces mounting with a particular kernel API, rather than selecting one automatically.
+    #[must_use]
+    pub fn api(mut self, api: MountApi) -> Self {
+        self.api = api;
+        self
+    }
+
     /// Offset for the loopback device
     #[cfg(feature = "loop")]
     #[must_use]
@@ -65,7 +95,7 @@ impl<'a> MountBuilder<'a> {
         self
     }
 
-    /// Use loopback even if not an iso or squashfs is being mounted
+    /// Use loopback even if the source is not a regular file, such as for a block device
     #[cfg(feature = "loop")]
     #[must_use]
     pub fn explicit_loopback(mut self) -> Self {
@@ -94,24 +124,45 @@ impl<'a> MountBuilder<'a> {
     /// # Notes
     ///
     /// The provided `source` device and `target` destinations must exist within the file system.
+    /// The `source` may also be a tag such as `UUID=…` or `PARTLABEL=…`, which is resolved to
+    /// a device with [`SourceTag::resolve`].
     ///
-    /// If the `source` is a file with an extension, a loopback device will be created, and the
-    /// file will be associated with the loopback device. If the extension is `iso` or `squashfs`,
-    /// the filesystem type will be set accordingly, and the `MountFlags` will also be modified to
-    /// ensure that the `MountFlags::RDONLY` flag is set before mounting.
+    /// If the `source` is a regular file, such as a disk image, a loopback device will be
+    /// created, and the file will be associated with the loopback device. This is skipped for
+    /// bind, move, and remount operations, which apply to the file itself, and for file systems
+    /// such as `tmpfs` which are not mounted from devices. If the file system is `iso9660`,
+    /// `squashfs`, or `erofs`, whether it was given or detected, the `MountFlags` will also be
+    /// modified to ensure that the `MountFlags::RDONLY` flag is set before mounting.
     ///
     /// The `fstype` parameter accepts either a `&str` or `&SupportedFilesystem` as input. If the
     /// input is a `&str`, then a particular file system will be used to mount the `source` with.
     /// If the input is a `&SupportedFilesystems`, then the file system will be selected
     /// automatically from the list.
     ///
-    /// The automatic variant of `fstype` works by attempting to mount the `source` with all
-    /// supported device-based file systems until it succeeds, or fails after trying all
-    /// possible options.
+    /// The automatic variant of `fstype` first reads the superblock of the `source` to detect
+    /// its file system, and mounts it with only that file system, returning its error if it
+    /// fails. If the file system is unknown, it falls back to attempting to mount the `source`
+    /// with all supported device-based file systems until it succeeds, or fails after trying
+    /// all possible options. A `Set` of file systems is likewise narrowed to the detected one,
+    /// when it is within the set.
+    ///
+    /// Where the kernel supports it, the mount is performed with `fsopen`, `fsconfig`, `fsmount`,
+    /// and `move_mount`, falling back to the legacy `mount(2)` system call on older kernels.
+    /// Bind, move, and remount operations always use `mount(2)`. The choice can be forced with
+    /// [`MountBuilder::api`].
+    ///
+    /// Either way, the messages logged by the file system on failure are attached to the
+    /// returned error as a [`FsContextError`](crate::FsContextError). For `mount(2)`, this is
+    /// done by replaying the parameters through `fsopen` when a manually-selected file system
+    /// rejects the mount with `EINVAL`, without creating the file system a second time.
     ///
     /// # Errors
     ///
     /// - If a fstype is not defined and supported filesystems cannot be detected
+    /// - If the source is a tag which could not be resolved to a single device
+    /// - If the file system type is to be detected, but the source holds an encrypted volume,
+    ///   an LVM physical volume, a RAID member, a bcache device, or swap, with a
+    ///   [`NotMountableError`](crate::NotMountableError)
     /// - If a loopback device cannot be created
     /// - If the source or target are not valid C strings
     /// - If mounting fails
@@ -120,6 +171,7 @@ impl<'a> MountBuilder<'a> {
             data,
             fstype,
             flags,
+            api,
             #[cfg(feature = "loop")]
             loopback_offset,
             #[cfg(feature = "loop")]
@@ -135,15 +187,69 @@ impl<'a> MountBuilder<'a> {
             FilesystemType::Auto(&supported)
         };
 
-        let source = source.as_ref();
+        let resolved;
+        let mut source = source.as_ref();
+
+        if let Some(tag) = source.to_str().and_then(SourceTag::parse) {
+            resolved = tag.resolve()?;
+            source = &resolved;
+        }
+
+        let mut flags = flags;
         let mut c_source = None;
 
         #[cfg(feature = "loop")]
-        let (mut flags, mut fstype, mut loopback, mut loop_path) = (flags, fstype, None, None);
+        let (mut loopback, mut loop_path) = (None, None);
+
+        // Operations on existing mounts have no file system to inspect, and nor do pseudo file
+        // systems, whose source is only a name, such as `tmpfs` or `none`.
+        let inspect = !source.as_os_str().is_empty()
+            && !flags.intersects(MountFlags::BIND | MountFlags::MOVE | MountFlags::REMOUNT)
+            && device_backed(fstype);
+
+        let metadata = inspect.then(|| fs::metadata(source).ok()).flatten();
+
+        #[cfg(feature = "loop")]
+        let looped = explicit_loopback || metadata.as_ref().is_some_and(fs::Metadata::is_file);
+
+        // The offset only applies to the file behind a loopback.
+        #[cfg(feature = "loop")]
+        let offset = if looped { loopback_offset } else { 0 };
+
+        #[cfg(not(feature = "loop"))]
+        let offset = 0;
+
+        // Only devices and images are read, so that a FIFO or socket can't block the open.
+        let superblock = metadata
+            .filter(|meta| meta.is_file() || meta.file_type().is_block_device())
+            .and_then(|_| File::open(source).ok())
+            .and_then(|file| probe::superblock_at(&file, offset));
+
+        let detected = match (&superblock, fstype) {
+            (_, FilesystemType::Manual(_)) | (None, _) => Vec::new(),
+            (Some(superblock), _) => match superblock.not_mountable(source) {
+                Some(why) => return Err(io::Error::new(io::ErrorKind::InvalidInput, why)),
+                None => superblock.drivers(),
+            },
+        };
+
+        // Formats which can not be written must be mounted, and their loopbacks attached,
+        // read-only.
+        let read_only = match fstype {
+            FilesystemType::Manual(fstype) => READ_ONLY_FORMATS.contains(&fstype),
+            _ => detected
+                .first()
+                .is_some_and(|fstype| READ_ONLY_FORMATS.contains(fstype)),
+        };
+
+        if read_only {
+            flags |= MountFlags::RDONLY;
+        }
 
         if !source.as_os_str().is_empty() {
+            // Create a loopback device if a regular file is being mounted.
             #[cfg(feature = "loop")]
-            let mut create_loopback = |flags: &MountFlags| -> io::Result<loopdev::LoopDevice> {
+            if looped {
                 let new_loopback = loopdev::LoopControl::open()?.next_free()?;
                 new_loopback
                     .with()
@@ -153,29 +259,7 @@ impl<'a> MountBuilder<'a> {
                 let path = new_loopback.path().expect("loopback does not have path");
                 c_source = Some(to_cstring(path.as_os_str().as_bytes())?);
                 loop_path = Some(path);
-                Ok(new_loopback)
-            };
-
-            // Create a loopback device if an iso or squashfs is being mounted.
-            #[cfg(feature = "loop")]
-            if let Some(ext) = source.extension() {
-                let extf = i32::from(ext == "iso") | if ext == "squashfs" { 2 } else { 0 };
-
-                if extf != 0 {
-                    fstype = if extf == 1 {
-                        flags |= MountFlags::RDONLY;
-                        FilesystemType::Manual("iso9660")
-                    } else {
-                        flags |= MountFlags::RDONLY;
-                        FilesystemType::Manual("squashfs")
-                    };
-                    loopback = Some(create_loopback(&flags)?);
-                }
-            }
-
-            #[cfg(feature = "loop")]
-            if loopback.is_none() && explicit_loopback {
-                loopback = Some(create_loopback(&flags)?);
+                loopback = Some(new_loopback);
             }
 
             if c_source.is_none() {
@@ -190,17 +274,40 @@ impl<'a> MountBuilder<'a> {
             None => None,
         };
 
+        let fs_context = match api {
+            MountApi::Auto => !flags.intersects(LEGACY_FLAGS) && fs_context_supported(),
+            MountApi::FsContext => !flags.intersects(LEGACY_FLAGS),
+            MountApi::Legacy => false,
+        };
+
         let mut mount_data = MountData {
             c_source,
             c_target,
             flags,
             data,
+            fs_context,
+            fallback: api == MountApi::Auto,
         };
 
         let mut res = match fstype {
-            FilesystemType::Auto(supported) => mount_data.automount(supported.dev_file_systems()),
-            FilesystemType::Set(set) => mount_data.automount(set.iter().copied()),
-            FilesystemType::Manual(fstype) => mount_data.mount(fstype),
+            // The kernel loads the modules of detected types that are not yet registered.
+            FilesystemType::Auto(supported) if detected.is_empty() => {
+                mount_data.automount(supported.dev_file_systems())
+            }
+            FilesystemType::Auto(_) => mount_data.mount_detected(&detected),
+            FilesystemType::Set(set) => {
+                let mut detected = detected;
+                detected.retain(|fstype| set.contains(fstype));
+
+                if detected.is_empty() {
+                    mount_data.automount(set.iter().copied())
+                } else {
+                    mount_data.mount_detected(&detected)
+                }
+            }
+            FilesystemType::Manual(fstype) => mount_data
+                .mount(fstype)
+                .map_err(|why| mount_data.explain(fstype, why)),
         };
 
         match res {
@@ -239,15 +346,81 @@ impl<'a> MountBuilder<'a> {
     }
 }
 
+/// Flags describing operations that are only possible with the legacy `mount(2)` call.
+const LEGACY_FLAGS: MountFlags = MountFlags::BIND
+    .union(MountFlags::MOVE)
+    .union(MountFlags::REMOUNT)
+    .union(MountFlags::REC)
+    .union(MountFlags::MANDLOCK);
+
+/// Whether the file system may be mounted from a device, and so its source should be read.
+///
+/// A file system which the kernel has not registered yet, as its module is not loaded, is
+/// assumed to be, unless it is `none`.
+fn device_backed(fstype: FilesystemType<'_>) -> bool {
+    let on_device = |supported: &SupportedFilesystems, fstype: &str| {
+        supported.dev_file_systems().any(|dev| dev == fstype)
+            || !(fstype == "none" || supported.is_supported(fstype))
+    };
+
+    match fstype {
+        FilesystemType::Auto(_) => true,
+        FilesystemType::Manual(fstype) => {
+            SupportedFilesystems::new().map_or(true, |supported| on_device(&supported, fstype))
+        }
+        FilesystemType::Set(set) => SupportedFilesystems::new().map_or(true, |supported| {
+            set.iter().any(|fstype| on_device(&supported, fstype))
+        }),
+    }
+}
+
+/// File systems which can only be mounted read-only.
+const READ_ONLY_FORMATS: [&str; 3] = ["iso9660", "squashfs", "erofs"];
+
+/// Checks once whether `fsopen` is available, or was either not built into the kernel or
+/// blocked by a seccomp filter.
+fn fs_context_supported() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+
+    *SUPPORTED.get_or_init(|| {
+        let result = unsafe { syscall(SYS_fsopen, ptr::null::<libc::c_char>(), 0) };
+        let errno = io::Error::last_os_error().raw_os_error();
+        result >= 0 || !matches!(errno, Some(ENOSYS | EPERM))
+    })
+}
+
 struct MountData {
     c_source: Option<CString>,
     c_target: CString,
     flags: MountFlags,
     data: Option<CString>,
+    fs_context: bool,
+    fallback: bool,
 }
 
 impl MountData {
     fn mount(&mut self, fstype: &str) -> io::Result<Mount> {
+        if self.fs_context {
+            match self.mount_fs_context(fstype) {
+                Err(why) if self.fallback && why.raw_os_error() == Some(ENOSYS) => {
+                    self.fs_context = false;
+                }
+                result => return result,
+            }
+        }
+
+        self.mount_legacy(fstype)
+    }
+
+    fn mount_fs_context(&self, fstype: &str) -> io::Result<Mount> {
+        let context = FsContext::new(fstype)?;
+        context.configure(self.c_source.as_deref(), self.data.as_deref(), self.flags)?;
+
+        let target = Path::new(OsStr::from_bytes(self.c_target.as_bytes()));
+        context.mount(self.flags)?.attach(target)
+    }
+
+    fn mount_legacy(&self, fstype: &str) -> io::Result<Mount> {
         let c_fstype = to_cstring(fstype.as_bytes())?;
         match mount_(
             self.c_source.as_ref(),
@@ -264,6 +437,40 @@ impl MountData {
         }
     }
 
+    /// Asks the kernel for the reason a mount failed, when it can give one.
+    fn explain(&self, fstype: &str, why: io::Error) -> io::Error {
+        if self.fs_context || self.flags.intersects(LEGACY_FLAGS) {
+            return why;
+        }
+
+        explain_mount_failure(
+            fstype,
+            self.c_source.as_deref(),
+            self.data.as_deref(),
+            self.flags,
+            why,
+        )
+    }
+
+    /// Mounts with the drivers of a detected file system, in order of preference, returning
+    /// the error of the first which is available rather than trying any other file system.
+    fn mount_detected(mut self, drivers: &[&str]) -> io::Result<Mount> {
+        let mut unavailable = None;
+
+        for &fstype in drivers {
+            match self.mount(fstype) {
+                Ok(mount) => return Ok(mount),
+                // The driver is not built, so the next is tried, such as ext4 for ext2.
+                Err(why) if why.raw_os_error() == Some(ENODEV) => {
+                    unavailable.get_or_insert(why);
+                }
+                Err(why) => return Err(self.explain(fstype, why)),
+            }
+        }
+
+        Err(unavailable.unwrap_or_else(|| io::Error::from_raw_os_error(ENODEV)))
+    }
+
     fn automount<'a, I: Iterator<Item = &'a str> + 'a>(mut self, iter: I) -> io::Result<Mount> {
         let mut res = Ok(());
 
@@ -308,3 +515,31 @@ fn mount_(
         _err => Err(io::Error::last_os_error()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "requires CAP_SYS_ADMIN"]
+    fn forced_fs_context_binds() {
+        let root = std::env::temp_dir().join(format!("sys-mount-bind-{}", std::process::id()));
+        let (source, target) = (root.join("source"), root.join("target"));
+        fs::create_dir_all(&source).unwrap();
+        fs::create_dir_all(&target).unwrap();
+        fs::write(source.join("marker"), "").unwrap();
+
+        let mount = MountBuilder::default()
+            .fstype("tmpfs")
+            .flags(MountFlags::BIND)
+            .api(MountApi::FsContext)
+            .mount(&source, &target)
+            .unwrap();
+
+        let bound = target.join("marker").exists();
+        mount.unmount(UnmountFlags::DETACH).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(bound, "a new file system was mounted instead of the source");
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright 2018-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::mount::mount_setattr_;
+use crate::{create_user_namespace, to_cstring, IdMap, Mount, MountAttr, PropagationType};
+use libc::{
+    c_uint, syscall, SYS_move_mount, SYS_open_tree, AT_EMPTY_PATH, AT_FDCWD, AT_RECURSIVE,
+    MOVE_MOUNT_F_EMPTY_PATH, OPEN_TREE_CLOEXEC, OPEN_TREE_CLONE,
+};
+use std::{
+    ffi::CStr,
+    io,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::ffi::OsStrExt,
+    },
+    path::Path,
+};
+
+/// The empty path, which refers to the file descriptor itself with `AT_EMPTY_PATH`.
+const EMPTY_PATH: &CStr = unsafe { CStr::from_bytes_with_nul_unchecked(b"\0") };
+
+/// A mount which is not attached to the file system tree, held open by a file descriptor.
+///
+/// The mount is not visible to anyone until it is attached with [`DetachedMount::attach`].
+/// If it is dropped without being attached, the kernel discards it.
+///
+/// ```no_run
+/// use sys_mount::*;
+///
+/// fn main() -> std::io::Result<()> {
+///     // Atomically bind `/srv/data` and everything mounted beneath it to `/mnt/data`.
+///     let _mount = DetachedMount::clone_tree("/srv/data", true)?.attach("/mnt/data")?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct DetachedMount {
+    fd: OwnedFd,
+    fstype: String,
+}
+
+impl DetachedMount {
+    #[inline]
+    pub(crate) fn new(fd: OwnedFd, fstype: String) -> Self {
+        DetachedMount { fd, fstype }
+    }
+
+    /// Clones the mount at `source` into a new detached mount with `open_tree`, much like a
+    /// bind mount that has not yet been attached anywhere.
+    ///
+    /// If `recursive` is set, the entire mount tree beneath `source` is cloned with it, as with
+    /// `MountFlags::BIND | MountFlags::REC`.
+    ///
+    /// The file descriptor of a detached mount remains valid across `setns`, so the clone can
+    /// be attached inside of another mount namespace.
+    ///
+    /// # Errors
+    ///
+    /// - If the source is not a valid C string
+    /// - If the kernel does not support `open_tree`, or the source cannot be cloned
+    pub fn clone_tree(source: impl AsRef<Path>, recursive: bool) -> io::Result<Self> {
+        let c_source = to_cstring(source.as_ref().as_os_str().as_bytes())?;
+
+        let mut flags = OPEN_TREE_CLONE | OPEN_TREE_CLOEXEC;
+        if recursive {
+            flags |= AT_RECURSIVE as c_uint;
+        }
+
+        let fd = unsafe { syscall(SYS_open_tree, AT_FDCWD, c_source.as_ptr(), flags) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        Ok(DetachedMount::new(fd, String::new()))
+    }
+
+    /// Describes the file system of this mount.
+    ///
+    /// This is empty for mounts cloned with [`DetachedMount::clone_tree`].
+    #[inline]
+    #[must_use]
+    pub fn get_fstype(&self) -> &str {
+        &self.fstype
+    }
+
+    /// Sets and clears per-mount attributes, and optionally changes the propagation type,
+    /// before the mount is attached.
+    ///
+    /// If `recursive` is set, the change applies to every mount within a recursively cloned
+    /// tree.
+    ///
+    /// # Errors
+    ///
+    /// If the kernel does not support `mount_setattr`, or rejects the change.
+    pub fn set_attr(
+        &self,
+        set: MountAttr,
+        clear: MountAttr,
+        propagation: Option<PropagationType>,
+        recursive: bool,
+    ) -> io::Result<()> {
+        let mut flags = AT_EMPTY_PATH as c_uint;
+        if recursive {
+            flags |= AT_RECURSIVE as c_uint;
+        }
+
+        mount_setattr_(
+            self.fd.as_raw_fd(),
+            EMPTY_PATH,
+            flags,
+            set,
+            clear,
+            propagation,
+            None,
+        )
+    }
+
+    /// Idmaps the mount through the mappings of the user namespace referred to by `userns`,
+    /// such that files are presented with their owners shifted accordingly.
+    ///
+    /// The kernel only permits idmapping a mount before it has been attached. If `recursive`
+    /// is set, every mount within a recursively cloned tree is idmapped.
+    ///
+    /// # Errors
+    ///
+    /// If the kernel or file system does not support idmapped mounts, or the mount is already
+    /// idmapped.
+    pub fn idmap(&self, userns: BorrowedFd<'_>, recursive: bool) -> io::Result<()> {
+        let mut flags = AT_EMPTY_PATH as c_uint;
+        if recursive {
+            flags |= AT_RECURSIVE as c_uint;
+        }
+
+        let empty = MountAttr::empty();
+        mount_setattr_(
+            self.fd.as_raw_fd(),
+            EMPTY_PATH,
+            flags,
+            empty,
+            empty,
+            None,
+            Some(userns),
+        )
+    }
+
+    /// Idmaps the mount through the given UID and GID mappings, using a throwaway user
+    /// namespace created with [`create_user_namespace`](crate::create_user_namespace).
+    ///
+    /// ```no_run
+    /// use sys_mount::*;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    ///     // Present files owned by root as owned by UID and GID 100000.
+    ///     let map = [IdMap::new(0, 100_000, 65536)];
+    ///     let detached = DetachedMount::clone_tree("/srv/rootfs", false)?;
+    ///     detached.idmap_with(&map, &map, false)?;
+    ///     let _mount = detached.attach("/run/container/rootfs")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - If the user namespace could not be created with the given mappings
+    /// - If the mount could not be idmapped
+    pub fn idmap_with(
+        &self,
+        uid_map: &[IdMap],
+        gid_map: &[IdMap],
+        recursive: bool,
+    ) -> io::Result<()> {
+        let userns = create_user_namespace(uid_map, gid_map)?;
+        self.idmap(userns.as_fd(), recursive)
+    }
+
+    /// Attaches the mount to the `target` path with `move_mount`.
+    ///
+    /// # Errors
+    ///
+    /// - If the target is not a valid C string
+    /// - If the kernel refuses to attach the mount at the target
+    pub fn attach(self, target: impl AsRef<Path>) -> io::Result<Mount> {
+        self.attach_at(None, target)
+    }
+
+    /// Attaches the mount to a `target` path relative to the `dir` directory.
+    ///
+    /// When `dir` is `None`, the path is relative to the current working directory.
+    ///
+    /// # Errors
+    ///
+    /// - If the target is not a valid C string
+    /// - If the path of `dir` could not be resolved
+    /// - If the kernel refuses to attach the mount at the target
+    pub fn attach_at(
+        self,
+        dir: Option<BorrowedFd<'_>>,
+        target: impl AsRef<Path>,
+    ) -> io::Result<Mount> {
+        let target = target.as_ref();
+        let c_target = to_cstring(target.as_os_str().as_bytes())?;
+
+        // Resolved beforehand, as a failure once attached would leave no handle to unmount.
+        let path = match dir {
+            Some(dir) if target.is_relative() => {
+                let dir = std::fs::read_link(format!("/proc/self/fd/{}", dir.as_raw_fd()))?;
+                to_cstring(dir.join(target).as_os_str().as_bytes())?
+            }
+            _ => c_target.clone(),
+        };
+
+        let result = unsafe {
+            syscall(
+                SYS_move_mount,
+                self.fd.as_raw_fd(),
+                EMPTY_PATH.as_ptr(),
+                dir.map_or(AT_FDCWD, |dir| dir.as_raw_fd()),
+                c_target.as_ptr(),
+                MOVE_MOUNT_F_EMPTY_PATH,
+            )
+        };
+
+        match result {
+            0 => Ok(Mount::from_target_and_fstype(path, self.fstype)),
+            _err => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+impl AsFd for DetachedMount {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for DetachedMount {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl From<DetachedMount> for OwnedFd {
+    fn from(mount: DetachedMount) -> Self {
+        mount.fd
+    }
+}
@@ -2,11 +2,14 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use libc::{
-    c_int, c_ulong, MNT_DETACH, MNT_EXPIRE, MNT_FORCE, MS_BIND, MS_DIRSYNC, MS_MANDLOCK, MS_MOVE,
-    MS_NOATIME, MS_NODEV, MS_NODIRATIME, MS_NOEXEC, MS_NOSUID, MS_PRIVATE, MS_RDONLY, MS_REC,
-    MS_RELATIME, MS_REMOUNT, MS_SHARED, MS_SILENT, MS_SLAVE, MS_STRICTATIME, MS_SYNCHRONOUS,
-    MS_UNBINDABLE, O_NOFOLLOW,
+    c_int, c_ulong, MNT_DETACH, MNT_EXPIRE, MNT_FORCE, MOUNT_ATTR_IDMAP, MOUNT_ATTR_NOATIME,
+    MOUNT_ATTR_NODEV, MOUNT_ATTR_NODIRATIME, MOUNT_ATTR_NOEXEC, MOUNT_ATTR_NOSUID,
+    MOUNT_ATTR_NOSYMFOLLOW, MOUNT_ATTR_RDONLY, MOUNT_ATTR_STRICTATIME, MS_BIND, MS_DIRSYNC,
+    MS_MANDLOCK, MS_MOVE, MS_NOATIME, MS_NODEV, MS_NODIRATIME, MS_NOEXEC, MS_NOSUID, MS_PRIVATE,
+    MS_RDONLY, MS_REC, MS_RELATIME, MS_REMOUNT, MS_SHARED, MS_SILENT, MS_SLAVE, MS_STRICTATIME,
+    MS_SYNCHRONOUS, MS_UNBINDABLE, O_NOFOLLOW,
 };
+use std::fmt::{self, Display, Formatter};
 
 bitflags! {
     /// Flags which may be specified when mounting a file system.
@@ -116,6 +119,129 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Per-mount attributes which may be set or cleared with `mount_setattr`, or applied to a
+    /// new mount with `fsmount`.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct MountAttr: u64 {
+        /// Make the mount read-only.
+        const RDONLY = MOUNT_ATTR_RDONLY;
+
+        /// Do not honor set-user-ID and set-group-ID bits when executing programs from this
+        /// mount.
+        const NOSUID = MOUNT_ATTR_NOSUID;
+
+        /// Do not allow access to devices (special files) on this mount.
+        const NODEV = MOUNT_ATTR_NODEV;
+
+        /// Do not allow programs to be executed from this mount.
+        const NOEXEC = MOUNT_ATTR_NOEXEC;
+
+        /// Do not update access times for (all types of) files on this mount.
+        ///
+        /// Neither this nor `STRICTATIME` being set means `relatime`, where access times are
+        /// only updated if they are older than the modification or status change times. Clear
+        /// either of them to switch a mount back to it.
+        const NOATIME = MOUNT_ATTR_NOATIME;
+
+        /// Always update the last access time when files on this mount are accessed.
+        const STRICTATIME = MOUNT_ATTR_STRICTATIME;
+
+        /// Do not update access times for directories on this mount.
+        const NODIRATIME = MOUNT_ATTR_NODIRATIME;
+
+        /// Do not follow symbolic links when resolving paths on this mount.
+        const NOSYMFOLLOW = MOUNT_ATTR_NOSYMFOLLOW;
+
+        /// Map the ownership of files on this mount through the mappings of a user namespace.
+        const IDMAP = MOUNT_ATTR_IDMAP;
+    }
+}
+
+/// The `mount(8)` option name of each `MountFlags` flag, in the order that they are listed.
+///
+/// `REC` has no option of its own, and is only written as part of `rbind`.
+pub(crate) const FLAG_OPTIONS: [(MountFlags, &str); 15] = [
+    (MountFlags::RDONLY, "ro"),
+    (MountFlags::NOSUID, "nosuid"),
+    (MountFlags::NODEV, "nodev"),
+    (MountFlags::NOEXEC, "noexec"),
+    (MountFlags::SYNCHRONOUS, "sync"),
+    (MountFlags::REMOUNT, "remount"),
+    (MountFlags::MANDLOCK, "mand"),
+    (MountFlags::DIRSYNC, "dirsync"),
+    (MountFlags::NOATIME, "noatime"),
+    (MountFlags::NODIRATIME, "nodiratime"),
+    (MountFlags::BIND, "bind"),
+    (MountFlags::MOVE, "move"),
+    (MountFlags::SILENT, "silent"),
+    (MountFlags::RELATIME, "relatime"),
+    (MountFlags::STRICTATIME, "strictatime"),
+];
+
+impl Display for MountFlags {
+    /// Writes the flags as `mount(8)` options, such as `ro,nosuid,noatime`.
+    ///
+    /// A recursive bind mount is written as `rbind`, and no flags at all as `defaults`. `REC`
+    /// is not otherwise written, as `mount(8)` has no option for it.
+    ///
+    /// ```rust
+    /// use sys_mount::MountFlags;
+    ///
+    /// let flags = MountFlags::RDONLY | MountFlags::BIND | MountFlags::REC;
+    /// assert_eq!(flags.to_string(), "ro,rbind");
+    /// assert_eq!((MountFlags::RDONLY | MountFlags::REC).to_string(), "ro");
+    /// assert_eq!(MountFlags::empty().to_string(), "defaults");
+    /// ```
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        let rbind = self.contains(MountFlags::BIND | MountFlags::REC);
+        let mut written = false;
+
+        for (flag, name) in FLAG_OPTIONS {
+            if !self.contains(flag) {
+                continue;
+            }
+
+            if written {
+                fmt.write_str(",")?;
+            }
+
+            fmt.write_str(if rbind && flag == MountFlags::BIND {
+                "rbind"
+            } else {
+                name
+            })?;
+
+            written = true;
+        }
+
+        if !written {
+            fmt.write_str("defaults")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<MountFlags> for MountAttr {
+    /// Converts the per-mount flags of a `MountFlags` into their `MountAttr` equivalents,
+    /// ignoring flags which apply to the file system rather than the mount.
+    fn from(flags: MountFlags) -> Self {
+        [
+            (MountFlags::RDONLY, MountAttr::RDONLY),
+            (MountFlags::NOSUID, MountAttr::NOSUID),
+            (MountFlags::NODEV, MountAttr::NODEV),
+            (MountFlags::NOEXEC, MountAttr::NOEXEC),
+            (MountFlags::NOATIME, MountAttr::NOATIME),
+            (MountFlags::STRICTATIME, MountAttr::STRICTATIME),
+            (MountFlags::NODIRATIME, MountAttr::NODIRATIME),
+        ]
+        .iter()
+        .filter(|(flag, _)| flags.contains(*flag))
+        .fold(MountAttr::empty(), |attr, (_, bit)| attr | *bit)
+    }
+}
+
 bitflags! {
     /// Flags which may be specified when unmounting a file system.
     #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -0,0 +1,475 @@
+// Copyright 2018-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::{to_cstring, DetachedMount, MountAttr, MountFlags};
+use libc::{
+    c_int, c_uint, c_void, syscall, SYS_fsconfig, SYS_fsmount, SYS_fsopen, SYS_fspick, AT_FDCWD,
+    EINVAL, FSCONFIG_CMD_CREATE, FSCONFIG_CMD_RECONFIGURE, FSCONFIG_SET_FD, FSCONFIG_SET_FLAG,
+    FSCONFIG_SET_PATH, FSCONFIG_SET_STRING, FSMOUNT_CLOEXEC, FSOPEN_CLOEXEC, FSPICK_CLOEXEC,
+};
+use std::{
+    ffi::{CStr, CString},
+    io,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::ffi::OsStrExt,
+    },
+    path::Path,
+    ptr,
+};
+
+/// An error returned by the kernel while configuring a [`FsContext`].
+///
+/// Records the parameter that was rejected, so that callers can tell exactly which option
+/// the file system did not accept, along with any messages the file system logged to the
+/// context explaining why.
+#[derive(Debug, Error)]
+#[error("{}: {source}{}", describe(.key.as_deref()), explain(.log))]
+#[allow(clippy::module_name_repetitions)]
+pub struct FsContextError {
+    key: Option<String>,
+    #[source]
+    source: io::Error,
+    log: Vec<String>,
+}
+
+impl FsContextError {
+    /// The parameter which was rejected, if the error originated from setting a parameter.
+    #[must_use]
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    /// The OS error code returned by the kernel.
+    #[must_use]
+    pub fn raw_os_error(&self) -> Option<i32> {
+        self.source.raw_os_error()
+    }
+
+    /// Messages logged by the file system, such as `e overlayfs: missing 'lowerdir'`.
+    ///
+    /// Each message is prefixed by its severity: `e` for errors, `w` for warnings, and `i`
+    /// for informational messages.
+    #[must_use]
+    pub fn log(&self) -> &[String] {
+        &self.log
+    }
+}
+
+impl From<FsContextError> for io::Error {
+    fn from(why: FsContextError) -> Self {
+        io::Error::new(why.source.kind(), why)
+    }
+}
+
+fn describe(key: Option<&str>) -> String {
+    key.map_or_else(
+        || "failed to configure file system".to_owned(),
+        |key| format!("failed to set `{}`", key),
+    )
+}
+
+fn explain(log: &[String]) -> String {
+    if log.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", log.join("; "))
+    }
+}
+
+/// A file system context created with `fsopen`, which is configured one parameter at a
+/// time before the file system is created and mounted with `fsmount`.
+///
+/// ```no_run
+/// use sys_mount::*;
+///
+/// fn main() -> std::io::Result<()> {
+///     let context = FsContext::new("tmpfs")?;
+///     context.set_string("size", "64M")?;
+///     context.set_string("mode", "0755")?;
+///
+///     let _mount = context
+///         .mount(MountFlags::NOSUID | MountFlags::NODEV)?
+///         .attach("/tmp/location")?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct FsContext {
+    fd: OwnedFd,
+    fstype: String,
+}
+
+impl FsContext {
+    /// Opens a new file system context for the `fstype` file system.
+    ///
+    /// # Errors
+    ///
+    /// - If `fstype` is not a valid C string
+    /// - If the kernel does not support `fsopen`, or does not know of the file system
+    pub fn new(fstype: &str) -> io::Result<Self> {
+        let c_fstype = to_cstring(fstype.as_bytes())?;
+        let fd = unsafe { syscall(SYS_fsopen, c_fstype.as_ptr(), FSOPEN_CLOEXEC) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(FsContext {
+            fd: unsafe { OwnedFd::from_raw_fd(fd as RawFd) },
+            fstype: fstype.to_owned(),
+        })
+    }
+
+    /// Picks the existing superblock of the mount at `target` with `fspick`, so that it may be
+    /// reconfigured with [`FsContext::reconfigure`].
+    pub(crate) fn pick(target: &CStr, fstype: &str) -> io::Result<Self> {
+        let fd = unsafe { syscall(SYS_fspick, AT_FDCWD, target.as_ptr(), FSPICK_CLOEXEC) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(FsContext {
+            fd: unsafe { OwnedFd::from_raw_fd(fd as RawFd) },
+            fstype: fstype.to_owned(),
+        })
+    }
+
+    /// The file system type this context was opened for.
+    #[must_use]
+    pub fn fstype(&self) -> &str {
+        &self.fstype
+    }
+
+    /// Sets a boolean parameter, such as `ro`.
+    ///
+    /// # Errors
+    ///
+    /// If the file system rejects the parameter.
+    pub fn set_flag(&self, key: &str) -> Result<(), FsContextError> {
+        let c_key = c_key(key)?;
+        self.config(FSCONFIG_SET_FLAG, Some(key), Some(&c_key), ptr::null(), 0)
+    }
+
+    /// Clears a boolean parameter by setting its negated form, such as `noacl` for `acl`.
+    ///
+    /// As `ro` is negated by `rw`, and `sync` by `async`, clearing either of a pair sets the
+    /// other. Any other key is negated by prefixing it with `no`, so a parameter which is
+    /// already negated, such as `noacl`, is cleared by setting its positive form with
+    /// [`FsContext::set_flag`] instead.
+    ///
+    /// # Errors
+    ///
+    /// If the file system rejects the parameter.
+    pub fn clear_flag(&self, key: &str) -> Result<(), FsContextError> {
+        self.set_flag(&negated(key))
+    }
+
+    /// Sets a parameter to a string value, such as `source` or `lowerdir`.
+    ///
+    /// # Errors
+    ///
+    /// If the file system rejects the parameter.
+    pub fn set_string(&self, key: &str, value: &str) -> Result<(), FsContextError> {
+        let c_value = to_cstring(value.as_bytes()).map_err(|why| error(Some(key), why))?;
+        self.set_c_string(key, &c_value)
+    }
+
+    pub(crate) fn set_c_string(&self, key: &str, value: &CStr) -> Result<(), FsContextError> {
+        let c_key = c_key(key)?;
+        let value = value.as_ptr().cast::<c_void>();
+        self.config(FSCONFIG_SET_STRING, Some(key), Some(&c_key), value, 0)
+    }
+
+    /// Sets a parameter to a path, which the kernel looks up relative to the current
+    /// working directory.
+    ///
+    /// # Errors
+    ///
+    /// If the file system rejects the parameter.
+    pub fn set_path(&self, key: &str, path: impl AsRef<Path>) -> Result<(), FsContextError> {
+        let c_key = c_key(key)?;
+        let c_path = to_cstring(path.as_ref().as_os_str().as_bytes())
+            .map_err(|why| error(Some(key), why))?;
+        let value = c_path.as_ptr().cast::<c_void>();
+        self.config(FSCONFIG_SET_PATH, Some(key), Some(&c_key), value, AT_FDCWD)
+    }
+
+    /// Sets a parameter to an open file descriptor.
+    ///
+    /// # Errors
+    ///
+    /// If the file system rejects the parameter.
+    pub fn set_fd(&self, key: &str, fd: BorrowedFd<'_>) -> Result<(), FsContextError> {
+        let c_key = c_key(key)?;
+        let fd = fd.as_raw_fd();
+        self.config(FSCONFIG_SET_FD, Some(key), Some(&c_key), ptr::null(), fd)
+    }
+
+    /// Applies a comma-separated option string, as given to `mount(2)` as its `data`.
+    ///
+    /// Each `key=value` pair is set as a string parameter, and each bare `key` as a flag.
+    ///
+    /// # Errors
+    ///
+    /// On the first parameter that the file system rejects.
+    pub fn set_data(&self, data: &str) -> Result<(), FsContextError> {
+        for option in split_options(data) {
+            match option.split_once('=') {
+                Some((key, value)) => self.set_string(key, value)?,
+                None => self.set_flag(option)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates the file system and mounts it as a [`DetachedMount`], which can then be
+    /// attached anywhere in the file system tree.
+    ///
+    /// Of the given `flags`, only the per-mount attributes (`RDONLY`, `NOSUID`, `NODEV`,
+    /// `NOEXEC`, and the access time flags) are applied.
+    ///
+    /// # Errors
+    ///
+    /// - If the file system could not be created from the configured parameters
+    /// - If the kernel could not mount the file system
+    pub fn mount(&self, flags: MountFlags) -> io::Result<DetachedMount> {
+        self.create()?;
+
+        let fd = unsafe {
+            syscall(
+                SYS_fsmount,
+                self.fd.as_raw_fd(),
+                FSMOUNT_CLOEXEC,
+                MountAttr::from(flags).bits() as c_uint,
+            )
+        };
+
+        if fd < 0 {
+            return Err(self.error(None, io::Error::last_os_error()).into());
+        }
+
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        Ok(DetachedMount::new(fd, self.fstype.clone()))
+    }
+
+    /// Configures the context with the arguments of an equivalent `mount(2)` call, setting
+    /// the superblock flags from `flags`; the per-mount flags are applied by `fsmount`.
+    pub(crate) fn configure(
+        &self,
+        source: Option<&CStr>,
+        data: Option<&CStr>,
+        flags: MountFlags,
+    ) -> Result<(), FsContextError> {
+        if let Some(source) = source {
+            self.set_c_string("source", source)?;
+        }
+
+        // `SILENT` only quietens the kernel log for `mount(2)`, and has no parameter here.
+        let superblock_flags = [
+            (MountFlags::RDONLY, "ro"),
+            (MountFlags::SYNCHRONOUS, "sync"),
+            (MountFlags::DIRSYNC, "dirsync"),
+        ];
+
+        for (flag, key) in superblock_flags {
+            if flags.contains(flag) {
+                self.set_flag(key)?;
+            }
+        }
+
+        if let Some(data) = data {
+            let data = data
+                .to_str()
+                .map_err(|why| error(None, io::Error::new(io::ErrorKind::InvalidData, why)))?;
+            self.set_data(data)?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn create(&self) -> Result<(), FsContextError> {
+        self.config(FSCONFIG_CMD_CREATE, None, None, ptr::null(), 0)
+    }
+
+    /// Applies the parameters that have been set to the superblock of an existing mount, for
+    /// a context obtained from [`Mount::reconfigure`](crate::Mount::reconfigure).
+    ///
+    /// Parameters which were not set are left unchanged.
+    ///
+    /// # Errors
+    ///
+    /// If the file system cannot apply the new configuration.
+    pub fn reconfigure(&self) -> Result<(), FsContextError> {
+        self.config(FSCONFIG_CMD_RECONFIGURE, None, None, ptr::null(), 0)
+    }
+
+    /// Reads and clears the messages that the file system has logged to this context.
+    ///
+    /// Each message is prefixed by its severity: `e` for errors, `w` for warnings, and `i`
+    /// for informational messages.
+    #[must_use]
+    pub fn read_log(&self) -> Vec<String> {
+        let mut log = Vec::new();
+        let mut buffer = [0u8; 4096];
+
+        loop {
+            let read = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    buffer.as_mut_ptr().cast::<c_void>(),
+                    buffer.len(),
+                )
+            };
+
+            let Ok(read @ 1..) = usize::try_from(read) else {
+                break;
+            };
+
+            let message = String::from_utf8_lossy(&buffer[..read]);
+            log.push(message.trim_end().to_owned());
+        }
+
+        log
+    }
+
+    fn error(&self, key: Option<&str>, source: io::Error) -> FsContextError {
+        FsContextError {
+            log: self.read_log(),
+            ..error(key, source)
+        }
+    }
+
+    fn config(
+        &self,
+        command: c_uint,
+        key: Option<&str>,
+        c_key: Option<&CString>,
+        value: *const c_void,
+        aux: c_int,
+    ) -> Result<(), FsContextError> {
+        let result = unsafe {
+            syscall(
+                SYS_fsconfig,
+                self.fd.as_raw_fd(),
+                command,
+                c_key.map_or_else(ptr::null, |k| k.as_ptr()),
+                value,
+                aux,
+            )
+        };
+
+        match result {
+            0 => Ok(()),
+            _err => Err(self.error(key, io::Error::last_os_error())),
+        }
+    }
+}
+
+impl AsFd for FsContext {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for FsContext {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// Explains why a legacy `mount(2)` call failed with `EINVAL`, by repeating the same
+/// parameters with `fsopen` and collecting the messages that the file system logs while
+/// rejecting them.
+///
+/// The file system is never created, so nothing is read from the source, and other errors,
+/// which do not stem from the configuration, are not replayed at all. Returns the original
+/// error unchanged if the kernel has nothing more to say.
+pub(crate) fn explain_mount_failure(
+    fstype: &str,
+    source: Option<&CStr>,
+    data: Option<&CStr>,
+    flags: MountFlags,
+    why: io::Error,
+) -> io::Error {
+    if why.raw_os_error() != Some(EINVAL) {
+        return why;
+    }
+
+    let probe = || -> Result<(), FsContextError> {
+        let context = FsContext::new(fstype).map_err(|why| error(None, why))?;
+        context.configure(source, data, flags)
+    };
+
+    match probe() {
+        Err(FsContextError { key, log, .. }) if !log.is_empty() => FsContextError {
+            key,
+            source: why,
+            log,
+        }
+        .into(),
+        _ => why,
+    }
+}
+
+fn c_key(key: &str) -> Result<CString, FsContextError> {
+    to_cstring(key.as_bytes()).map_err(|why| error(Some(key), why))
+}
+
+fn error(key: Option<&str>, source: io::Error) -> FsContextError {
+    FsContextError {
+        key: key.map(str::to_owned),
+        source,
+        log: Vec::new(),
+    }
+}
+
+/// Splits a `mount(2)` data string on commas, ignoring commas within double quotes.
+pub(crate) fn split_options(data: &str) -> impl Iterator<Item = &str> {
+    let mut quoted = false;
+    data.split(move |c| {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        c == ',' && !quoted
+    })
+    .filter(|option| !option.is_empty())
+}
+
+/// The flag which clears `key`.
+fn negated(key: &str) -> String {
+    match key {
+        "ro" => "rw".to_owned(),
+        "rw" => "ro".to_owned(),
+        "sync" => "async".to_owned(),
+        "async" => "sync".to_owned(),
+        key => ["no", key].concat(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negated_flags() {
+        assert_eq!(negated("ro"), "rw");
+        assert_eq!(negated("rw"), "ro");
+        assert_eq!(negated("async"), "sync");
+        assert_eq!(negated("acl"), "noacl");
+        assert_eq!(negated("noload"), "nonoload");
+    }
+
+    #[test]
+    #[ignore = "requires CAP_SYS_ADMIN"]
+    fn configure_ignores_silent() {
+        let context = FsContext::new("tmpfs").unwrap();
+
+        let flags = MountFlags::SILENT | MountFlags::RDONLY;
+        context.configure(None, None, flags).unwrap();
+        context.create().unwrap();
+    }
+}
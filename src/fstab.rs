@@ -0,0 +1,843 @@
+// Copyright 2018-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::fs_context::split_options;
+use crate::mountinfo::unescape;
+use crate::{Mount, MountFlags, MountOptions, MountTable, Mounts, Unmount, UnmountFlags};
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::{self, File},
+    io::{self, Write},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt, io::AsRawFd},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+/// Options for [`Fstab::mount_all`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct MountAllOptions {
+    root: PathBuf,
+    unmount_flags: Option<UnmountFlags>,
+}
+
+impl MountAllOptions {
+    /// Mounts every entry beneath `root`, such as `/mnt/target` when mounting the file systems
+    /// of another installation. Bind mount sources are taken to be beneath `root` as well.
+    #[must_use]
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = root.into();
+        self
+    }
+
+    /// The flags with which the returned [`Mounts`] are unmounted when dropped.
+    #[must_use]
+    pub fn unmount_flags(mut self, flags: UnmountFlags) -> Self {
+        self.unmount_flags = Some(flags);
+        self
+    }
+
+    /// Places `path` beneath the root.
+    fn prefix(&self, path: &Path) -> PathBuf {
+        if self.root.as_os_str().is_empty() {
+            return path.to_owned();
+        }
+
+        path.components()
+            .filter(|component| !matches!(component, Component::RootDir))
+            .fold(self.root.clone(), |prefixed, component| {
+                prefixed.join(component)
+            })
+    }
+}
+
+/// What [`Fstab::mount_all`] did with an entry.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct MountReport {
+    /// The entry from the fstab.
+    pub entry: FstabEntry,
+    /// Where the entry was to be mounted, beneath the root.
+    pub target: PathBuf,
+    /// Whether the entry was mounted.
+    pub outcome: MountOutcome,
+}
+
+/// Whether an entry was mounted by [`Fstab::mount_all`].
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub enum MountOutcome {
+    /// The entry was mounted.
+    Mounted,
+    /// The entry was not mounted, for the given reason.
+    Skipped(SkipReason),
+    /// Mounting failed, but the entry is marked `nofail`.
+    Failed(io::Error),
+}
+
+/// Why [`Fstab::mount_all`] did not mount an entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SkipReason {
+    /// The entry is marked `noauto`.
+    NoAuto,
+    /// The entry is swap space, rather than a file system.
+    Swap,
+    /// Something is already mounted at the target.
+    AlreadyMounted,
+    /// The target is beneath an entry which failed to mount.
+    ParentFailed,
+}
+
+/// A file system to mount, as described by a line of `/etc/fstab`.
+///
+/// Fields are stored unescaped, and are escaped with octal sequences when written, as
+/// documented in `fstab(5)`.
+///
+/// ```rust
+/// use std::path::Path;
+/// use sys_mount::FstabEntry;
+///
+/// let entry = "LABEL=My\\040Data /mnt/data ext4 defaults,noatime 0 2"
+///     .parse::<FstabEntry>()
+///     .unwrap();
+///
+/// assert_eq!(entry.spec, "LABEL=My Data");
+/// assert_eq!(entry.file, Path::new("/mnt/data"));
+/// assert_eq!(entry.passno, 2);
+/// assert_eq!(entry.to_string(), "LABEL=My\\040Data\t/mnt/data\text4\tdefaults,noatime\t0\t2");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[allow(clippy::module_name_repetitions)]
+pub struct FstabEntry {
+    /// The block device or remote file system to mount, such as `/dev/sda1` or `UUID=...`.
+    pub spec: String,
+    /// Where the file system is mounted, or `none` for swap.
+    pub file: PathBuf,
+    /// The file system type, such as `ext4`, `swap`, or `auto`.
+    pub vfstype: String,
+    /// Comma-separated mount options, such as `defaults` or `ro,noatime`.
+    pub mntops: String,
+    /// Whether the file system should be dumped by `dump(8)`.
+    pub freq: u32,
+    /// The order in which `fsck(8)` checks the file system, where `0` skips it.
+    pub passno: u32,
+}
+
+impl FstabEntry {
+    /// Describes a new entry, which is neither dumped nor checked.
+    pub fn new(
+        spec: impl Into<String>,
+        file: impl Into<PathBuf>,
+        vfstype: impl Into<String>,
+        mntops: impl Into<String>,
+    ) -> Self {
+        FstabEntry {
+            spec: spec.into(),
+            file: file.into(),
+            vfstype: vfstype.into(),
+            mntops: mntops.into(),
+            freq: 0,
+            passno: 0,
+        }
+    }
+
+    /// The fields of this entry, escaped and ready to be written.
+    fn fields(&self) -> [String; 6] {
+        [
+            escape(self.spec.as_bytes()),
+            escape(self.file.as_os_str().as_bytes()),
+            escape(self.vfstype.as_bytes()),
+            escape(self.mntops.as_bytes()),
+            self.freq.to_string(),
+            self.passno.to_string(),
+        ]
+    }
+}
+
+impl FromStr for FstabEntry {
+    type Err = io::Error;
+
+    fn from_str(line: &str) -> io::Result<Self> {
+        parse_entry(line).map(|(entry, _)| entry)
+    }
+}
+
+impl Display for FstabEntry {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        fmt.write_str(&self.fields().join("\t"))
+    }
+}
+
+/// The contents of an fstab file, which is written back with its comments, blank lines, and
+/// column alignment intact.
+///
+/// Entries which are not modified are written exactly as they were read. Modified entries
+/// keep the alignment of their original line, and new entries are aligned with the last
+/// entry in the file.
+///
+/// ```rust
+/// use sys_mount::{Fstab, FstabEntry};
+///
+/// let mut fstab = "\
+/// ## <file system> <mount point> <type> <options> <dump> <pass>
+/// /dev/sda2        /             ext4   defaults  0      1
+///
+/// /dev/sda1        /boot/efi     vfat   umask=077 0      0
+/// ".parse::<Fstab>().unwrap();
+///
+/// fstab.find_mut("/").unwrap().mntops = "noatime".into();
+/// fstab.push(FstabEntry::new("/dev/sda3", "/home", "ext4", "defaults"));
+///
+/// assert_eq!(fstab.to_string(), "\
+/// ## <file system> <mount point> <type> <options> <dump> <pass>
+/// /dev/sda2        /             ext4   noatime   0      1
+///
+/// /dev/sda1        /boot/efi     vfat   umask=077 0      0
+/// /dev/sda3        /home         ext4   defaults  0      0
+/// ");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Fstab {
+    lines: Vec<Line>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Line {
+    /// A comment or blank line.
+    Text(String),
+    /// An entry, along with how it was originally written, if it was read from a file.
+    Entry(FstabEntry, Option<Box<Original>>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Original {
+    entry: FstabEntry,
+    line: String,
+    layout: Layout,
+}
+
+/// The whitespace surrounding the fields of a line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Layout {
+    indent: String,
+    fields: Vec<String>,
+    separators: Vec<String>,
+    trailing: String,
+}
+
+impl Fstab {
+    /// Reads and parses the fstab file at `path`, such as `/etc/fstab`.
+    ///
+    /// # Errors
+    ///
+    /// If the file could not be read, or contains an invalid entry.
+    pub fn from_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Writes the fstab to `path`, replacing the file atomically.
+    ///
+    /// The new file is synced to disk before it replaces the original, and keeps the mode and
+    /// owner of the original.
+    ///
+    /// # Errors
+    ///
+    /// If the file could not be written.
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+
+        let mut file = File::create(&temporary)?;
+        file.write_all(self.to_string().as_bytes())?;
+
+        match fs::metadata(path) {
+            Ok(original) => {
+                if unsafe { libc::fchown(file.as_raw_fd(), original.uid(), original.gid()) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                file.set_permissions(original.permissions())?;
+            }
+            Err(why) if why.kind() == io::ErrorKind::NotFound => (),
+            Err(why) => return Err(why),
+        }
+
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+    }
+
+    /// Iterates over the entries, in the order they appear.
+    pub fn entries(&self) -> impl Iterator<Item = &FstabEntry> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Entry(entry, _) => Some(entry),
+            Line::Text(_) => None,
+        })
+    }
+
+    /// Iterates mutably over the entries, in the order they appear.
+    pub fn entries_mut(&mut self) -> impl Iterator<Item = &mut FstabEntry> {
+        self.lines.iter_mut().filter_map(|line| match line {
+            Line::Entry(entry, _) => Some(entry),
+            Line::Text(_) => None,
+        })
+    }
+
+    /// Finds the last entry which mounts to `file`, which is the one that takes effect.
+    #[must_use]
+    pub fn find(&self, file: impl AsRef<Path>) -> Option<&FstabEntry> {
+        self.entries()
+            .filter(|entry| entry.file == file.as_ref())
+            .last()
+    }
+
+    /// Finds the last entry which mounts to `file`, for modification.
+    pub fn find_mut(&mut self, file: impl AsRef<Path>) -> Option<&mut FstabEntry> {
+        self.entries_mut()
+            .filter(|entry| entry.file == file.as_ref())
+            .last()
+    }
+
+    /// Appends an entry to the end of the file.
+    pub fn push(&mut self, entry: FstabEntry) {
+        self.lines.push(Line::Entry(entry, None));
+    }
+
+    /// Removes every entry for which `keep` returns `false`, leaving comments in place.
+    pub fn retain(&mut self, mut keep: impl FnMut(&FstabEntry) -> bool) {
+        self.lines.retain(|line| match line {
+            Line::Entry(entry, _) => keep(entry),
+            Line::Text(_) => true,
+        });
+    }
+
+    /// Mounts every entry, like `mount -a`, returning the mounts along with a report of what
+    /// was done with each entry.
+    ///
+    /// Entries are mounted in order of their mount points, so that `/` is mounted before
+    /// `/home`, and `/home` before `/home/user/data`. An entry whose source lies beneath the
+    /// mount point of another entry, such as a bind mount source or an overlay `lowerdir`, is
+    /// mounted after that entry. Entries marked `noauto`, swap entries, and entries whose
+    /// targets are already mount points, including those mounted by an earlier entry, are
+    /// skipped. The target
+    /// directory is created first for entries with the `x-mount.mkdir` option. Sources such
+    /// as `UUID=…` are resolved to devices with [`SourceTag::resolve`](crate::SourceTag::resolve).
+    ///
+    /// ```no_run
+    /// use sys_mount::*;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    ///     let fstab = Fstab::from_path("/mnt/target/etc/fstab")?;
+    ///     let (mounts, report) = fstab.mount_all(&MountAllOptions::default().root("/mnt/target"))?;
+    ///
+    ///     for entry in &report {
+    ///         println!("{}: {:?}", entry.target.display(), entry.outcome);
+    ///     }
+    ///
+    ///     // Everything is unmounted in reverse when `mounts` is dropped.
+    ///     drop(mounts);
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the mount table could not be read, or an entry which is not marked `nofail` could
+    /// not be mounted. Everything which was mounted before the failure is unmounted again.
+    pub fn mount_all(&self, options: &MountAllOptions) -> io::Result<(Mounts, Vec<MountReport>)> {
+        let unmount_flags = options.unmount_flags.unwrap_or(UnmountFlags::empty());
+        let table = MountTable::list()?;
+        let mut mounted = table
+            .iter()
+            .map(|info| info.mount_point.clone())
+            .collect::<Vec<_>>();
+
+        let entries = mount_order(self.entries().collect());
+
+        let mut mounts = Mounts(Vec::new());
+        let mut reports = Vec::with_capacity(entries.len());
+        let mut failed: Vec<PathBuf> = Vec::new();
+
+        for entry in entries {
+            let target = options.prefix(&entry.file);
+            let mount_options = MountOptions::parse(&entry.mntops);
+
+            let skip = if mount_options.has_userspace("noauto") {
+                Some(SkipReason::NoAuto)
+            } else if entry.vfstype == "swap" {
+                Some(SkipReason::Swap)
+            } else if failed.iter().any(|parent| target.starts_with(parent)) {
+                Some(SkipReason::ParentFailed)
+            } else if mounted.contains(&target) {
+                Some(SkipReason::AlreadyMounted)
+            } else {
+                None
+            };
+
+            let outcome = match skip {
+                Some(reason) => MountOutcome::Skipped(reason),
+                None => match mount_entry(entry, &target, &mount_options, options) {
+                    Ok(mount) => {
+                        mounts.0.push(mount.into_unmount_drop(unmount_flags));
+                        mounted.push(target.clone());
+                        MountOutcome::Mounted
+                    }
+                    Err(why) if mount_options.has_userspace("nofail") => {
+                        failed.push(target.clone());
+                        MountOutcome::Failed(why)
+                    }
+                    Err(why) => {
+                        return Err(io::Error::new(
+                            why.kind(),
+                            format!(
+                                "failed to mount {} on {}: {}",
+                                entry.spec,
+                                target.display(),
+                                why
+                            ),
+                        ));
+                    }
+                },
+            };
+
+            reports.push(MountReport {
+                entry: entry.clone(),
+                target,
+                outcome,
+            });
+        }
+
+        Ok((mounts, reports))
+    }
+
+    /// Appends a comment line, which is prefixed with `#` if it is not already.
+    pub fn push_comment(&mut self, comment: &str) {
+        let comment = if comment.starts_with('#') {
+            comment.to_owned()
+        } else {
+            format!("# {}", comment)
+        };
+
+        self.lines.push(Line::Text(comment));
+    }
+}
+
+impl FromStr for Fstab {
+    type Err = io::Error;
+
+    fn from_str(contents: &str) -> io::Result<Self> {
+        let lines = contents
+            .lines()
+            .enumerate()
+            .map(|(number, line)| {
+                let content = line.trim_start();
+                if content.is_empty() || content.starts_with('#') {
+                    return Ok(Line::Text(line.to_owned()));
+                }
+
+                let (entry, layout) = parse_entry(line).map_err(|why| {
+                    io::Error::new(why.kind(), format!("line {}: {}", number + 1, why))
+                })?;
+
+                let original = Original {
+                    entry: entry.clone(),
+                    line: line.to_owned(),
+                    layout,
+                };
+
+                Ok(Line::Entry(entry, Some(Box::new(original))))
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Fstab { lines })
+    }
+}
+
+impl Display for Fstab {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        // New entries are aligned with the last entry which was read.
+        let mut template = None;
+
+        for line in &self.lines {
+            match line {
+                Line::Text(text) => writeln!(fmt, "{}", text)?,
+                Line::Entry(entry, Some(original)) if *entry == original.entry => {
+                    template = Some(&original.layout);
+                    writeln!(fmt, "{}", original.line)?;
+                }
+                Line::Entry(entry, original) => {
+                    let layout = original.as_ref().map(|original| &original.layout);
+                    template = layout.or(template);
+                    writeln!(fmt, "{}", render(entry, layout, template))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Mounts a single entry with a [`MountBuilder`](crate::MountBuilder).
+fn mount_entry(
+    entry: &FstabEntry,
+    target: &Path,
+    mount_options: &MountOptions,
+    options: &MountAllOptions,
+) -> io::Result<Mount> {
+    if mount_options.has_userspace("x-mount.mkdir") {
+        fs::create_dir_all(target)?;
+    }
+
+    let source = if mount_options.flags.contains(MountFlags::BIND) {
+        options.prefix(Path::new(&entry.spec))
+    } else {
+        PathBuf::from(&entry.spec)
+    };
+
+    let mut builder = Mount::builder().flags(mount_options.flags);
+
+    if entry.vfstype != "auto" {
+        builder = builder.fstype(entry.vfstype.as_str());
+    }
+
+    if !mount_options.data.is_empty() {
+        builder = builder.data(&mount_options.data);
+    }
+
+    #[cfg(feature = "loop")]
+    if mount_options.has_userspace("loop") {
+        builder = builder.explicit_loopback();
+
+        let offset = mount_options
+            .userspace
+            .iter()
+            .find_map(|option| option.strip_prefix("offset="))
+            .and_then(|offset| offset.parse::<u64>().ok());
+
+        if let Some(offset) = offset {
+            builder = builder.loopback_offset(offset);
+        }
+    }
+
+    let mount = builder.mount(source, target)?;
+
+    if let Some((propagation, recursive)) = mount_options.propagation {
+        if let Err(why) = mount.set_propagation(propagation, recursive) {
+            let _ = mount.unmount(UnmountFlags::DETACH);
+            return Err(why);
+        }
+    }
+
+    Ok(mount)
+}
+
+/// Orders entries by their mount points, moving each entry after any entry whose mount point
+/// holds its own, or one of its sources. Entries which depend on each other keep the order of
+/// their mount points.
+fn mount_order(mut entries: Vec<&FstabEntry>) -> Vec<&FstabEntry> {
+    entries.sort_by(|a, b| a.file.cmp(&b.file));
+
+    let sources = entries
+        .iter()
+        .map(|entry| source_paths(entry))
+        .collect::<Vec<_>>();
+
+    let depends = |index: usize, on: usize| {
+        let target = &entries[on].file;
+        (entries[index].file != *target && entries[index].file.starts_with(target))
+            || sources[index]
+                .iter()
+                .any(|source| source.starts_with(target))
+    };
+
+    let mut pending = (0..entries.len()).collect::<Vec<_>>();
+    let mut ordered = Vec::with_capacity(entries.len());
+
+    while !pending.is_empty() {
+        let next = pending
+            .iter()
+            .position(|&index| {
+                !pending
+                    .iter()
+                    .any(|&other| other != index && depends(index, other))
+            })
+            .unwrap_or(0);
+
+        ordered.push(entries[pending.remove(next)]);
+    }
+
+    ordered
+}
+
+/// The paths which an entry mounts from: its source, if it is a path, and the directories of
+/// an overlay.
+fn source_paths(entry: &FstabEntry) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(&entry.spec)];
+
+    for option in split_options(&entry.mntops) {
+        let Some((key, value)) = option.split_once('=') else {
+            continue;
+        };
+
+        if matches!(key, "lowerdir" | "lowerdir+" | "upperdir" | "workdir") {
+            let dirs = value.trim_matches('"').split(':');
+            paths.extend(dirs.filter(|dir| !dir.is_empty()).map(PathBuf::from));
+        }
+    }
+
+    paths.retain(|path| path.is_absolute());
+    paths
+}
+
+/// Splits a line into its entry, and the layout of its fields.
+fn parse_entry(line: &str) -> io::Result<(FstabEntry, Layout)> {
+    let invalid = |why: &str| io::Error::new(io::ErrorKind::InvalidData, why.to_owned());
+
+    let mut layout = Layout {
+        indent: String::new(),
+        fields: Vec::new(),
+        separators: Vec::new(),
+        trailing: String::new(),
+    };
+
+    let mut rest = line;
+    loop {
+        let field_start = rest.find(|c: char| !c.is_ascii_whitespace());
+        let whitespace = &rest[..field_start.unwrap_or(rest.len())];
+
+        if layout.fields.is_empty() {
+            layout.indent = whitespace.to_owned();
+        } else if field_start.is_some() {
+            layout.separators.push(whitespace.to_owned());
+        } else {
+            layout.trailing = whitespace.to_owned();
+        }
+
+        let Some(start) = field_start else {
+            break;
+        };
+
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        layout.fields.push(rest[..end].to_owned());
+        rest = &rest[end..];
+    }
+
+    let fields = &layout.fields;
+    if fields.len() < 4 {
+        return Err(invalid("expected at least four fields"));
+    } else if fields.len() > 6 {
+        return Err(invalid("expected at most six fields"));
+    }
+
+    let number = |index: usize| -> io::Result<u32> {
+        fields.get(index).map_or(Ok(0), |field| {
+            field
+                .parse::<u32>()
+                .map_err(|_| invalid(&format!("invalid number: '{}'", field)))
+        })
+    };
+
+    let text = |index: usize| unescape(&fields[index]).to_string_lossy().into_owned();
+
+    let entry = FstabEntry {
+        spec: text(0),
+        file: PathBuf::from(unescape(&fields[1])),
+        vfstype: text(2),
+        mntops: text(3),
+        freq: number(4)?,
+        passno: number(5)?,
+    };
+
+    Ok((entry, layout))
+}
+
+/// Writes an entry with the `layout` of its original line, or else that of the `template`.
+fn render(entry: &FstabEntry, layout: Option<&Layout>, template: Option<&Layout>) -> String {
+    let fields = entry.fields();
+
+    // Trailing zeroes may be omitted, as they were in the original line.
+    let count = match layout {
+        Some(layout) if entry.passno == 0 && (entry.freq == 0 || layout.fields.len() == 5) => {
+            layout.fields.len().max(if entry.freq == 0 { 4 } else { 5 })
+        }
+        _ => 6,
+    };
+
+    let Some(style) = layout.or(template) else {
+        return fields[..count].join("\t");
+    };
+
+    let mut line = style.indent.clone();
+    let mut column = width(&line);
+    let mut original_column = column;
+
+    for (index, field) in fields[..count].iter().enumerate() {
+        line.push_str(field);
+        column += width(field);
+
+        if index + 1 == count {
+            break;
+        }
+
+        let original = style.fields.get(index);
+        let separator = style
+            .separators
+            .get(index)
+            .or_else(|| style.separators.last())
+            .map_or("\t", String::as_str);
+
+        original_column +=
+            original.map_or_else(|| width(field), |original| width(original)) + width(separator);
+
+        // Pad with spaces so that the next field begins in its original column.
+        if separator.bytes().all(|byte| byte == b' ') && !separator.is_empty() {
+            let padding = original_column.saturating_sub(column).max(1);
+            line.push_str(&" ".repeat(padding));
+            column += padding;
+        } else {
+            line.push_str(separator);
+            column = original_column;
+        }
+    }
+
+    if layout.is_some() {
+        line.push_str(&style.trailing);
+    }
+
+    line
+}
+
+fn width(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Encodes the characters which would otherwise split or end a field as octal escapes, along
+/// with a leading `#`, which would otherwise make a line of the field a comment.
+pub(crate) fn escape(field: &[u8]) -> String {
+    let mut escaped = String::with_capacity(field.len());
+
+    let mut rest = field;
+    while !rest.is_empty() {
+        // Splits off the longest valid prefix, and the invalid sequence which follows it.
+        let (valid, invalid) = match std::str::from_utf8(rest) {
+            Ok(_) => (rest.len(), 0),
+            Err(why) => (
+                why.valid_up_to(),
+                why.error_len().unwrap_or(rest.len() - why.valid_up_to()),
+            ),
+        };
+
+        let (valid, after) = rest.split_at(valid);
+        let (invalid, after) = after.split_at(invalid);
+        rest = after;
+
+        for character in std::str::from_utf8(valid).unwrap_or_default().chars() {
+            match character {
+                ' ' | '\t' | '\n' | '\\' => {
+                    escaped.push_str(&format!("\\{:03o}", u32::from(character)));
+                }
+                '#' if escaped.is_empty() => {
+                    escaped.push_str(&format!("\\{:03o}", u32::from(character)));
+                }
+                character => escaped.push(character),
+            }
+        }
+
+        for byte in invalid {
+            escaped.push_str(&format!("\\{:03o}", byte));
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let contents = "\
+# <file system>  <mount point>  <type>  <options>  <dump>  <pass>
+UUID=1234        /              ext4    defaults   0       1
+
+LABEL=données    /srv           ext4    noatime    0       2
+tmpfs            /tmp           tmpfs   defaults   0       0
+";
+        let mut fstab = contents.parse::<Fstab>().unwrap();
+        assert_eq!(fstab.to_string(), contents);
+
+        fstab.find_mut("/srv").unwrap().mntops = "ro".into();
+        fstab.find_mut("/tmp").unwrap().spec = "#tmp fs".into();
+        fstab.push(FstabEntry::new(
+            "/dev/sdb1",
+            "/mnt/my disk",
+            "ext4",
+            "defaults",
+        ));
+
+        let expected = "\
+# <file system>  <mount point>  <type>  <options>  <dump>  <pass>
+UUID=1234        /              ext4    defaults   0       1
+
+LABEL=données    /srv           ext4    ro         0       2
+\\043tmp\\040fs    /tmp           tmpfs   defaults   0       0
+/dev/sdb1        /mnt/my\\040disk ext4   defaults   0       0
+";
+        assert_eq!(fstab.to_string(), expected);
+
+        let reread = expected.parse::<Fstab>().unwrap();
+        assert!(reread.entries().eq(fstab.entries()));
+        assert_eq!(reread.to_string(), expected);
+    }
+
+    #[test]
+    fn mount_order_follows_sources() {
+        let fstab = "\
+/srv/data /home/data none bind 0 0
+overlay /merged overlay lowerdir=/lower:/srv/lower,upperdir=/srv/upper,workdir=/srv/work 0 0
+/dev/sdb1 /srv ext4 defaults 0 2
+/dev/sda2 /home ext4 defaults 0 2
+/dev/sda1 / ext4 defaults 0 1
+"
+        .parse::<Fstab>()
+        .unwrap();
+
+        let order = mount_order(fstab.entries().collect())
+            .into_iter()
+            .map(|entry| entry.file.to_str().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(order, ["/", "/home", "/srv", "/home/data", "/merged"]);
+    }
+    #[test]
+    #[ignore = "requires CAP_SYS_ADMIN"]
+    fn mount_all_skips_repeated_targets() {
+        let root = std::env::temp_dir().join(format!("sys-mount-fstab-{}", std::process::id()));
+        let fstab = "\
+tmpfs /data tmpfs x-mount.mkdir 0 0
+tmpfs /data tmpfs x-mount.mkdir 0 0
+"
+        .parse::<Fstab>()
+        .unwrap();
+
+        let (mounts, reports) = fstab
+            .mount_all(&MountAllOptions::default().root(&root))
+            .unwrap();
+
+        let outcomes = reports
+            .iter()
+            .map(|report| match report.outcome {
+                MountOutcome::Skipped(reason) => Some(reason),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        drop(mounts);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(outcomes, [None, Some(SkipReason::AlreadyMounted)]);
+    }
+}
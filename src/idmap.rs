@@ -0,0 +1,96 @@
+// Copyright 2018-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::namespace::with_child;
+use libc::CLONE_NEWUSER;
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io,
+    os::fd::OwnedFd,
+};
+
+/// A single range of IDs to map, as written to `/proc/<pid>/uid_map` or `gid_map`.
+///
+/// `count` consecutive IDs starting from `inside` in the user namespace correspond to the
+/// same number of IDs starting from `outside` in the parent namespace. On an idmapped mount,
+/// a file owned by `inside` on disk is presented as owned by `outside`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IdMap {
+    /// The first ID of the range within the user namespace.
+    pub inside: u32,
+    /// The first ID of the range within the parent namespace.
+    pub outside: u32,
+    /// The number of IDs in the range.
+    pub count: u32,
+}
+
+impl IdMap {
+    /// Maps `count` IDs starting from `inside` onto IDs starting from `outside`.
+    #[must_use]
+    pub const fn new(inside: u32, outside: u32, count: u32) -> Self {
+        IdMap {
+            inside,
+            outside,
+            count,
+        }
+    }
+}
+
+/// Creates a throwaway user namespace with the given mappings, returning a file descriptor
+/// to it.
+///
+/// A child process is forked into a new user namespace just long enough for its mappings to
+/// be written and its namespace to be opened. The namespace lives on for as long as the
+/// returned file descriptor, or any mount idmapped with it, remains open.
+///
+/// # Errors
+///
+/// - If the child process could not be created, or could not create a user namespace
+/// - If the kernel rejects the mappings
+pub fn create_user_namespace(uid_map: &[IdMap], gid_map: &[IdMap]) -> io::Result<OwnedFd> {
+    with_child(
+        || unsafe { libc::unshare(CLONE_NEWUSER) == 0 },
+        |pid| {
+            let namespace = File::open(format!("/proc/{}/ns/user", pid))?;
+            fs::write(format!("/proc/{}/uid_map", pid), format_map(uid_map))?;
+
+            // Without `CAP_SETGID`, the kernel only accepts a gid_map once `setgroups` has
+            // been denied, so that groups can't be dropped to escape negative permissions.
+            if !has_cap_setgid() {
+                fs::write(format!("/proc/{}/setgroups", pid), "deny")?;
+            }
+
+            fs::write(format!("/proc/{}/gid_map", pid), format_map(gid_map))?;
+            Ok(OwnedFd::from(namespace))
+        },
+        || {
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "failed to create user namespace",
+            )
+        },
+    )
+}
+
+/// Whether the calling process has `CAP_SETGID` in its effective capabilities.
+fn has_cap_setgid() -> bool {
+    const CAP_SETGID: u32 = 6;
+
+    fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("CapEff:"))
+                .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        })
+        .is_some_and(|caps| caps & (1 << CAP_SETGID) != 0)
+}
+
+fn format_map(map: &[IdMap]) -> String {
+    map.iter().fold(String::new(), |mut out, id| {
+        let _ = writeln!(out, "{} {} {}", id.inside, id.outside, id.count);
+        out
+    })
+}
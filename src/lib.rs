@@ -3,8 +3,11 @@
 
 //! High level abstraction over the `mount` and `umount2` system calls.
 //!
+//! Also exposes the file descriptor based mount API (`fsopen`, `fsconfig`, `fsmount` and
+//! `move_mount`) through [`FsContext`] and [`DetachedMount`].
+//!
 //! If the `loop` feature is enabled (default), additionally supports creating loopback devices
-//! automatically when mounting an iso or squashfs file.
+//! automatically when mounting a regular file, such as an iso, squashfs, or disk image.
 //!
 //! # Example
 //!
@@ -51,13 +54,29 @@ extern crate bitflags;
 extern crate thiserror;
 
 mod builder;
+mod detached;
 mod flags;
+mod fs_context;
+mod fstab;
 mod fstype;
+mod idmap;
 mod mount;
+mod mountinfo;
+mod namespace;
+mod options;
+mod probe;
+mod source;
 mod supported;
+mod table;
+mod tree;
 mod umount;
+mod watch;
 
-pub use self::{builder::*, flags::*, fstype::*, mount::*, supported::*, umount::*};
+pub use self::{
+    builder::*, detached::*, flags::*, fs_context::*, fstab::*, fstype::*, idmap::*, mount::*,
+    mountinfo::*, namespace::*, options::*, probe::*, source::*, supported::*, table::*, tree::*,
+    umount::*, watch::*,
+};
 
 use libc::swapoff as c_swapoff;
 use std::{
@@ -1,13 +1,22 @@
 // Copyright 2018-2022 System76 <info@system76.com>
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+use crate::table::unique_ids;
 use crate::umount::{unmount_, Unmount, UnmountDrop};
-use crate::{MountBuilder, PropagationType, UnmountFlags};
+use crate::{FsContext, MountAttr, MountBuilder, PropagationType, UnmountFlags};
+use libc::{
+    c_uint, mount_attr, syscall, SYS_mount_setattr, AT_FDCWD, AT_RECURSIVE, MOUNT_ATTR_SIZE_VER0,
+    MOUNT_ATTR__ATIME, MS_REC, STATX_MNT_ID, STATX_MNT_ID_UNIQUE,
+};
 use std::ptr;
 use std::{
-    ffi::{CString, OsStr},
+    ffi::{CStr, CString, OsStr},
     io,
-    os::unix::ffi::OsStrExt,
+    mem::MaybeUninit,
+    os::{
+        fd::{AsRawFd, BorrowedFd, RawFd},
+        unix::ffi::OsStrExt,
+    },
     path::Path,
 };
 
@@ -16,6 +25,7 @@ use std::{
 pub struct Mount {
     pub(crate) target: CString,
     pub(crate) fstype: String,
+    pub(crate) mount_id: Option<u64>,
     #[cfg(feature = "loop")]
     pub(crate) loopback: Option<loopdev::LoopDevice>,
     pub(crate) loop_path: Option<std::path::PathBuf>,
@@ -97,16 +107,42 @@ impl Mount {
         Path::new(OsStr::from_bytes(self.target.as_bytes()))
     }
 
-    /// Change the propagation type of the mount.
+    /// The ID of this mount, for matching it against the entries of a [`MountTable`](crate::MountTable).
+    ///
+    /// This is the unique 64-bit mount ID where [`MountTable::list`](crate::MountTable::list)
+    /// lists mounts with `listmount`, or otherwise the reusable ID found in
+    /// `/proc/self/mountinfo`. It is `None` if the ID could not be determined.
     #[inline]
     #[must_use]
+    pub fn mount_id(&self) -> Option<u64> {
+        self.mount_id
+    }
+
+    /// Change the propagation type of the mount.
+    ///
+    /// # Errors
+    ///
+    /// If the propagation type could not be changed.
+    #[inline]
     pub fn set_propagation_type(&mut self, propagation_type: PropagationType) -> io::Result<()> {
+        self.set_propagation(propagation_type, false)
+    }
+
+    /// Changes the propagation type of the mount, and of every mount beneath it if
+    /// `recursive` is set.
+    pub(crate) fn set_propagation(
+        &self,
+        propagation_type: PropagationType,
+        recursive: bool,
+    ) -> io::Result<()> {
+        let rec = if recursive { MS_REC } else { 0 };
+
         let result = unsafe {
             libc::mount(
                 ptr::null(),
                 self.target.as_ptr(),
                 ptr::null(),
-                propagation_type.bits(),
+                propagation_type.bits() | rec,
                 ptr::null(),
             )
         };
@@ -117,9 +153,70 @@ impl Mount {
         }
     }
 
+    /// Opens the superblock of this mount with `fspick` for reconfiguration.
+    ///
+    /// Options may then be set or cleared one at a time on the returned context, each of
+    /// which is individually validated by the file system, before being applied together
+    /// with [`FsContext::reconfigure`].
+    ///
+    /// ```no_run
+    /// use sys_mount::*;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    ///     let mount = Mount::builder().fstype("tmpfs").mount("tmpfs", "/tmp/location")?;
+    ///
+    ///     let context = mount.reconfigure()?;
+    ///     context.set_string("size", "128M")?;
+    ///     context.set_flag("ro")?;
+    ///     context.reconfigure()?;
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the kernel does not support `fspick`, or the mount no longer exists.
+    pub fn reconfigure(&self) -> io::Result<FsContext> {
+        FsContext::pick(&self.target, &self.fstype)
+    }
+
+    /// Sets and clears per-mount attributes, and optionally changes the propagation type, in
+    /// a single atomic `mount_setattr` call.
+    ///
+    /// If `recursive` is set, the change is applied to every mount in the subtree beneath this
+    /// mount as well, so that an entire subtree can be made read-only at once.
+    ///
+    /// ```no_run
+    /// use sys_mount::*;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    ///     let mount = Mount::builder()
+    ///         .flags(MountFlags::BIND | MountFlags::REC)
+    ///         .mount("/srv/data", "/mnt/data")?;
+    ///
+    ///     mount.set_attr(MountAttr::RDONLY | MountAttr::NOSUID, MountAttr::empty(), None, true)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the kernel does not support `mount_setattr`, or rejects the change.
+    pub fn set_attr(
+        &self,
+        set: MountAttr,
+        clear: MountAttr,
+        propagation: Option<PropagationType>,
+        recursive: bool,
+    ) -> io::Result<()> {
+        let flags = if recursive { AT_RECURSIVE as c_uint } else { 0 };
+        mount_setattr_(AT_FDCWD, &self.target, flags, set, clear, propagation, None)
+    }
+
     #[inline]
     pub(crate) fn from_target_and_fstype(target: CString, fstype: String) -> Self {
         Mount {
+            mount_id: mount_id_of(&target, unique_ids()).ok(),
             target,
             fstype,
             #[cfg(feature = "loop")]
@@ -129,6 +226,84 @@ impl Mount {
     }
 }
 
+/// Fetches the ID of the mount containing `path` with `statx`.
+///
+/// If `unique` is set, the unique mount ID is preferred where the kernel supports it.
+/// Otherwise, the reusable ID from `/proc/self/mountinfo` is returned.
+pub(crate) fn mount_id_of(path: &CStr, unique: bool) -> io::Result<u64> {
+    let mask = if unique {
+        STATX_MNT_ID_UNIQUE | STATX_MNT_ID
+    } else {
+        STATX_MNT_ID
+    };
+
+    let mut stat = MaybeUninit::<libc::statx>::zeroed();
+    let result = unsafe { libc::statx(AT_FDCWD, path.as_ptr(), 0, mask, stat.as_mut_ptr()) };
+
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let stat = unsafe { stat.assume_init() };
+
+    if stat.stx_mask & mask == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "kernel does not report mount IDs",
+        ));
+    }
+
+    Ok(stat.stx_mnt_id)
+}
+
+pub(crate) fn mount_setattr_(
+    dir: RawFd,
+    path: &CStr,
+    flags: c_uint,
+    set: MountAttr,
+    clear: MountAttr,
+    propagation: Option<PropagationType>,
+    userns: Option<BorrowedFd<'_>>,
+) -> io::Result<()> {
+    let mut attr_clr = clear.bits();
+
+    // Access times are an enumeration rather than a set of bits, so the kernel requires the
+    // whole field to be cleared whenever any of them are changed.
+    if (set | clear).intersects(MountAttr::NOATIME | MountAttr::STRICTATIME) {
+        attr_clr |= MOUNT_ATTR__ATIME;
+    }
+
+    // `c_ulong` is only 32 bits wide on 32-bit targets.
+    #[allow(clippy::useless_conversion)]
+    let mut attr = mount_attr {
+        attr_set: set.bits(),
+        attr_clr,
+        propagation: propagation.map_or(0, |p| u64::from(p.bits())),
+        userns_fd: 0,
+    };
+
+    if let Some(userns) = userns {
+        attr.attr_set |= MountAttr::IDMAP.bits();
+        attr.userns_fd = userns.as_raw_fd() as u64;
+    }
+
+    let result = unsafe {
+        syscall(
+            SYS_mount_setattr,
+            dir,
+            path.as_ptr(),
+            flags,
+            ptr::addr_of_mut!(attr),
+            MOUNT_ATTR_SIZE_VER0 as usize,
+        )
+    };
+
+    match result {
+        0 => Ok(()),
+        _err => Err(io::Error::last_os_error()),
+    }
+}
+
 /// An abstraction that will ensure that temporary mounts are dropped in reverse.
 pub struct Mounts(pub Vec<UnmountDrop<Mount>>);
 
@@ -158,3 +333,48 @@ impl Drop for Mounts {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    #[ignore = "requires CAP_SYS_ADMIN"]
+    fn clearing_noatime_restores_relatime() {
+        let target = std::env::temp_dir().join(format!("sys-mount-atime-{}", std::process::id()));
+        fs::create_dir_all(&target).unwrap();
+
+        let options = |target: &Path| {
+            let mountinfo = fs::read_to_string("/proc/self/mountinfo").unwrap();
+            let target = target.to_str().unwrap();
+            let line = mountinfo
+                .lines()
+                .rev()
+                .find(|line| line.split(' ').nth(4) == Some(target))
+                .unwrap();
+
+            line.split(' ').nth(5).unwrap().to_owned()
+        };
+
+        let mount = Mount::builder()
+            .fstype("tmpfs")
+            .mount("tmpfs", &target)
+            .unwrap();
+
+        mount
+            .set_attr(MountAttr::NOATIME, MountAttr::empty(), None, false)
+            .unwrap();
+        let noatime = options(&target);
+        mount
+            .set_attr(MountAttr::empty(), MountAttr::NOATIME, None, false)
+            .unwrap();
+        let relatime = options(&target);
+
+        mount.unmount(UnmountFlags::DETACH).unwrap();
+        fs::remove_dir(&target).unwrap();
+
+        assert!(noatime.split(',').any(|option| option == "noatime"));
+        assert!(relatime.split(',').any(|option| option == "relatime"));
+    }
+}
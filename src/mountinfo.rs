@@ -0,0 +1,259 @@
+// Copyright 2018-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::{MountFlags, PropagationType};
+use std::{ffi::OsString, io, os::unix::ffi::OsStringExt, path::PathBuf, str::FromStr};
+
+/// A mount, as described by a line of `/proc/self/mountinfo`.
+///
+/// ```rust
+/// use std::path::Path;
+/// use sys_mount::{MountFlags, MountInfo, PropagationType};
+///
+/// let line = "36 35 98:0 /mnt1 /mnt\\0402 rw,noatime master:1 - ext3 /dev/root rw,errors=continue";
+/// let info = line.parse::<MountInfo>().unwrap();
+///
+/// assert_eq!(info.mount_id, 36);
+/// assert_eq!((info.major, info.minor), (98, 0));
+/// assert_eq!(info.mount_point, Path::new("/mnt 2"));
+/// assert_eq!(info.optional_fields, ["master:1"]);
+/// assert_eq!(info.flags(), MountFlags::NOATIME);
+/// assert_eq!(info.propagation_type(), PropagationType::SLAVE);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[allow(clippy::module_name_repetitions)]
+pub struct MountInfo {
+    /// The ID of this mount.
+    ///
+    /// When listed with `listmount`, this is the unique 64-bit mount ID, which is never reused
+    /// while the system is running. Otherwise it is the reusable ID from `/proc/self/mountinfo`.
+    pub mount_id: u64,
+    /// The ID of the parent mount, which is the mount's own ID for the root of the tree.
+    pub parent_id: u64,
+    /// The major device number of the file system.
+    pub major: u32,
+    /// The minor device number of the file system.
+    pub minor: u32,
+    /// The directory within the file system which forms the root of this mount.
+    pub root: PathBuf,
+    /// Where the mount is mounted, relative to the process's root directory.
+    pub mount_point: PathBuf,
+    /// Options which apply to this mount, such as `rw,nosuid,relatime`.
+    pub mount_options: String,
+    /// Tagged fields describing the propagation of this mount, such as `shared:1`.
+    pub optional_fields: Vec<String>,
+    /// The file system type, such as `ext4`.
+    pub fstype: String,
+    /// The file system specific source of the mount, such as `/dev/sda1`.
+    pub source: String,
+    /// Options which apply to the file system's superblock, shared by all of its mounts.
+    pub super_options: String,
+}
+
+impl MountInfo {
+    /// Parses a line of `/proc/self/mountinfo`, whose fields are documented in `proc(5)`.
+    pub(crate) fn parse_line(line: &str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid mountinfo line: '{}'", line),
+            )
+        };
+
+        let mut fields = line.split(' ');
+        let mut next = || fields.next().ok_or_else(invalid);
+
+        let mount_id = next()?.parse::<u64>().map_err(|_| invalid())?;
+        let parent_id = next()?.parse::<u64>().map_err(|_| invalid())?;
+        let (major, minor) = next()?.split_once(':').ok_or_else(invalid)?;
+        let major = major.parse::<u32>().map_err(|_| invalid())?;
+        let minor = minor.parse::<u32>().map_err(|_| invalid())?;
+        let root = PathBuf::from(unescape(next()?));
+        let mount_point = PathBuf::from(unescape(next()?));
+        let mount_options = next()?.to_owned();
+
+        // A variable number of optional fields is terminated by a single hyphen.
+        let mut optional_fields = Vec::new();
+        loop {
+            match next()? {
+                "-" => break,
+                "" => (),
+                field => optional_fields.push(field.to_owned()),
+            }
+        }
+
+        let fstype = unescape(next()?).to_string_lossy().into_owned();
+        let source = unescape(next()?).to_string_lossy().into_owned();
+        let super_options = next()?.to_owned();
+
+        Ok(MountInfo {
+            mount_id,
+            parent_id,
+            major,
+            minor,
+            root,
+            mount_point,
+            mount_options,
+            optional_fields,
+            fstype,
+            source,
+            super_options,
+        })
+    }
+
+    /// The `MountFlags` equivalent to the options of this mount and its superblock.
+    ///
+    /// File system specific options, which have no equivalent flag, are ignored.
+    ///
+    /// ```rust
+    /// use sys_mount::{MountFlags, MountInfo};
+    ///
+    /// let flags = |line: &str| line.parse::<MountInfo>().unwrap().flags();
+    ///
+    /// assert_eq!(
+    ///     flags("25 1 8:2 / /home rw,relatime shared:1 - ext4 /dev/sda2 rw"),
+    ///     MountFlags::RELATIME
+    /// );
+    /// assert_eq!(
+    ///     flags("26 1 8:3 / /srv ro,nosuid,strictatime - ext4 /dev/sda3 ro"),
+    ///     MountFlags::RDONLY | MountFlags::NOSUID | MountFlags::STRICTATIME
+    /// );
+    /// ```
+    #[must_use]
+    pub fn flags(&self) -> MountFlags {
+        let options = self
+            .mount_options
+            .split(',')
+            .chain(self.super_options.split(','));
+
+        options.fold(MountFlags::empty(), |flags, option| {
+            flags
+                | match option {
+                    "ro" => MountFlags::RDONLY,
+                    "nosuid" => MountFlags::NOSUID,
+                    "nodev" => MountFlags::NODEV,
+                    "noexec" => MountFlags::NOEXEC,
+                    "noatime" => MountFlags::NOATIME,
+                    "nodiratime" => MountFlags::NODIRATIME,
+                    "relatime" => MountFlags::RELATIME,
+                    "strictatime" => MountFlags::STRICTATIME,
+                    "sync" => MountFlags::SYNCHRONOUS,
+                    "dirsync" => MountFlags::DIRSYNC,
+                    "mand" => MountFlags::MANDLOCK,
+                    _ => MountFlags::empty(),
+                }
+        })
+    }
+
+    /// The peer group and master relationships of this mount, parsed from its optional
+    /// fields.
+    #[must_use]
+    pub fn propagation(&self) -> Propagation {
+        let mut propagation = Propagation::default();
+
+        for field in &self.optional_fields {
+            let (tag, value) = field.split_once(':').unwrap_or((field, ""));
+            let group = value.parse::<u64>().ok();
+
+            match tag {
+                "shared" => propagation.shared = group,
+                "master" => propagation.master = group,
+                "propagate_from" => propagation.propagate_from = group,
+                "unbindable" => propagation.unbindable = true,
+                _ => (),
+            }
+        }
+
+        propagation
+    }
+
+    /// The propagation type of this mount, derived from its optional fields.
+    ///
+    /// A mount which is a slave of one peer group while sharing events with another is both
+    /// `SHARED` and `SLAVE`.
+    #[must_use]
+    pub fn propagation_type(&self) -> PropagationType {
+        self.propagation().propagation_type()
+    }
+}
+
+/// How mount and unmount events propagate to and from a mount, as described by the optional
+/// fields of mountinfo.
+///
+/// ```rust
+/// use sys_mount::{MountInfo, PropagationType};
+///
+/// let line = "61 24 0:52 / /mnt rw shared:5 master:2 - tmpfs tmpfs rw";
+/// let propagation = line.parse::<MountInfo>().unwrap().propagation();
+///
+/// assert_eq!(propagation.shared, Some(5));
+/// assert_eq!(propagation.master, Some(2));
+/// assert_eq!(
+///     propagation.propagation_type(),
+///     PropagationType::SHARED | PropagationType::SLAVE
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Propagation {
+    /// The peer group this mount shares events with, if it is shared.
+    pub shared: Option<u64>,
+    /// The peer group this mount receives events from, if it is a slave.
+    pub master: Option<u64>,
+    /// The closest dominant peer group in this process's mount namespace that the mount
+    /// receives events from, if it differs from the master and is not visible itself.
+    pub propagate_from: Option<u64>,
+    /// Whether the mount is unbindable.
+    pub unbindable: bool,
+}
+
+impl Propagation {
+    /// The equivalent propagation type, which is `PRIVATE` when neither shared, a slave, or
+    /// unbindable.
+    #[must_use]
+    pub fn propagation_type(&self) -> PropagationType {
+        let mut propagation = PropagationType::empty();
+        propagation.set(PropagationType::SHARED, self.shared.is_some());
+        propagation.set(PropagationType::SLAVE, self.master.is_some());
+        propagation.set(PropagationType::UNBINDABLE, self.unbindable);
+
+        if propagation.is_empty() {
+            PropagationType::PRIVATE
+        } else {
+            propagation
+        }
+    }
+}
+
+impl FromStr for MountInfo {
+    type Err = io::Error;
+
+    fn from_str(line: &str) -> io::Result<Self> {
+        MountInfo::parse_line(line)
+    }
+}
+
+/// Decodes the octal escapes with which the kernel encodes spaces, tabs, newlines, and
+/// backslashes in mount table fields.
+pub(crate) fn unescape(field: &str) -> OsString {
+    let bytes = field.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let octal = bytes
+            .get(index + 1..index + 4)
+            .filter(|_| bytes[index] == b'\\')
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+
+        if let Some(byte) = octal {
+            decoded.push(byte);
+            index += 4;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+
+    OsString::from_vec(decoded)
+}
@@ -0,0 +1,125 @@
+// Copyright 2018-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use libc::{c_int, c_void, pid_t, O_CLOEXEC};
+use std::{
+    fs, io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::fs::MetadataExt,
+    },
+};
+
+/// Whether the processes `pid` and `other` share a mount namespace, as determined by
+/// comparing the nsfs inodes of their `/proc/<pid>/ns/mnt` links.
+///
+/// ```no_run
+/// use sys_mount::shares_mount_namespace;
+///
+/// fn main() -> std::io::Result<()> {
+///     if !shares_mount_namespace(std::process::id(), 1)? {
+///         println!("running within a container");
+///     }
+///
+///     Ok(())
+/// }
+/// ```
+///
+/// # Errors
+///
+/// If the namespace of either process could not be inspected, such as when the process has
+/// exited, or belongs to another user.
+pub fn shares_mount_namespace(pid: u32, other: u32) -> io::Result<bool> {
+    let namespace = |pid: u32| {
+        fs::metadata(format!("/proc/{}/ns/mnt", pid)).map(|meta| (meta.dev(), meta.ino()))
+    };
+
+    Ok(namespace(pid)? == namespace(other)?)
+}
+
+/// Forks a child process which runs `child`, and then calls `parent` with the child's PID
+/// while the child waits to be released.
+///
+/// `child` runs between `fork` and `_exit` in what may have been a multi-threaded process,
+/// so it must only make async-signal-safe calls, and must not allocate. It returns whether
+/// it succeeded, and if it did not, the result of `parent` is replaced with `failure`.
+pub(crate) fn with_child<T>(
+    child: impl FnOnce() -> bool,
+    parent: impl FnOnce(pid_t) -> io::Result<T>,
+    failure: impl FnOnce() -> io::Error,
+) -> io::Result<T> {
+    let (ready_read, ready_write) = pipe()?;
+    let (exit_read, exit_write) = pipe()?;
+
+    let pid = unsafe { libc::fork() };
+
+    if pid < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if pid == 0 {
+        // Only async-signal-safe calls are permitted between `fork` and `_exit`.
+        unsafe {
+            libc::close(ready_read.as_raw_fd());
+            libc::close(exit_write.as_raw_fd());
+            let status = c_int::from(!child());
+            let byte = [0u8];
+            libc::write(ready_write.as_raw_fd(), byte.as_ptr().cast::<c_void>(), 1);
+            let mut byte = [0u8];
+            libc::read(exit_read.as_raw_fd(), byte.as_mut_ptr().cast::<c_void>(), 1);
+            libc::_exit(status);
+        }
+    }
+
+    drop(ready_write);
+    drop(exit_read);
+
+    let result = (|| {
+        let mut byte = [0u8];
+        let read = unsafe {
+            libc::read(
+                ready_read.as_raw_fd(),
+                byte.as_mut_ptr().cast::<c_void>(),
+                1,
+            )
+        };
+
+        if read != 1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        parent(pid)
+    })();
+
+    drop(exit_write);
+    let status = wait(pid)?;
+
+    match result {
+        Ok(_) if status != 0 => Err(failure()),
+        result => result,
+    }
+}
+
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+
+    match unsafe { libc::pipe2(fds.as_mut_ptr(), O_CLOEXEC) } {
+        0 => unsafe { Ok((OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1]))) },
+        _err => Err(io::Error::last_os_error()),
+    }
+}
+
+fn wait(pid: pid_t) -> io::Result<c_int> {
+    let mut status = 0;
+
+    loop {
+        if unsafe { libc::waitpid(pid, &mut status, 0) } >= 0 {
+            return Ok(libc::WEXITSTATUS(status));
+        }
+
+        let why = io::Error::last_os_error();
+        if why.kind() != io::ErrorKind::Interrupted {
+            return Err(why);
+        }
+    }
+}
@@ -0,0 +1,292 @@
+// Copyright 2018-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::flags::FLAG_OPTIONS;
+use crate::fs_context::split_options;
+use crate::{MountFlags, PropagationType};
+use std::fmt::{self, Display, Formatter};
+
+/// Options which clear a flag that a preceding option may have set.
+const CLEAR_OPTIONS: [(MountFlags, &str); 10] = [
+    (MountFlags::RDONLY, "rw"),
+    (MountFlags::NOSUID, "suid"),
+    (MountFlags::NODEV, "dev"),
+    (MountFlags::NOEXEC, "exec"),
+    (MountFlags::SYNCHRONOUS, "async"),
+    (MountFlags::MANDLOCK, "nomand"),
+    (MountFlags::NOATIME, "atime"),
+    (MountFlags::NODIRATIME, "diratime"),
+    (MountFlags::RELATIME, "norelatime"),
+    (MountFlags::STRICTATIME, "nostrictatime"),
+];
+
+/// Userspace options which imply flags, unless they are overridden by a later option, as in
+/// `user,exec`.
+const IMPLYING_OPTIONS: [(&str, MountFlags); 4] = [
+    (
+        "user",
+        MountFlags::NOEXEC
+            .union(MountFlags::NOSUID)
+            .union(MountFlags::NODEV),
+    ),
+    (
+        "users",
+        MountFlags::NOEXEC
+            .union(MountFlags::NOSUID)
+            .union(MountFlags::NODEV),
+    ),
+    ("owner", MountFlags::NOSUID.union(MountFlags::NODEV)),
+    ("group", MountFlags::NOSUID.union(MountFlags::NODEV)),
+];
+
+/// Options which change the propagation type of the mount once it is mounted, each of which
+/// also has an `r` prefixed form that applies to every mount beneath it.
+const PROPAGATION_OPTIONS: [(PropagationType, &str); 4] = [
+    (PropagationType::SHARED, "shared"),
+    (PropagationType::SLAVE, "slave"),
+    (PropagationType::PRIVATE, "private"),
+    (PropagationType::UNBINDABLE, "unbindable"),
+];
+
+/// A comma-separated option string, such as the options field of fstab, split into the
+/// arguments of a [`MountBuilder`](crate::MountBuilder).
+///
+/// Options with a `MountFlags` equivalent become flags, propagation options such as `rshared`
+/// are set aside to be applied after mounting, and so are options which are only meaningful
+/// to userspace tools such as `mount(8)`. Everything else is passed to the file system as data.
+///
+/// As with `mount(8)`, `user` and `users` imply `noexec,nosuid,nodev`, and `owner` and `group`
+/// imply `nosuid,nodev`, unless a later option such as `exec` overrides them.
+///
+/// ```rust
+/// use sys_mount::{MountFlags, MountOptions, PropagationType};
+///
+/// let options =
+///     MountOptions::parse("ro,noatime,nosuid,subvol=@home,compress=zstd,rslave,nofail");
+///
+/// assert_eq!(
+///     options.flags,
+///     MountFlags::RDONLY | MountFlags::NOATIME | MountFlags::NOSUID
+/// );
+/// assert_eq!(options.data, "subvol=@home,compress=zstd");
+/// assert_eq!(options.propagation, Some((PropagationType::SLAVE, true)));
+/// assert_eq!(options.userspace, ["nofail"]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash, smart_default::SmartDefault)]
+#[allow(clippy::module_name_repetitions)]
+pub struct MountOptions {
+    /// Options with an equivalent flag.
+    #[default(MountFlags::empty())]
+    pub flags: MountFlags,
+    /// File system specific options, as a comma-separated `mount(2)` data string.
+    pub data: String,
+    /// The propagation type to change the mount to once mounted, and whether to change every
+    /// mount beneath it too. Where several are given, the last is kept.
+    pub propagation: Option<(PropagationType, bool)>,
+    /// Options for userspace tools, such as `noauto`, `nofail`, `user`, `_netdev`, and `x-*`.
+    pub userspace: Vec<String>,
+}
+
+impl MountOptions {
+    /// Splits an option string, applying options from left to right, such that `ro,rw` is
+    /// read-write. Commas within double quotes do not separate options.
+    #[must_use]
+    pub fn parse(options: &str) -> Self {
+        let mut parsed = MountOptions::default();
+
+        for option in split_options(options) {
+            if option == "defaults" {
+                // Equivalent to `rw,suid,dev,exec,auto,nouser,async`.
+                parsed.flags.remove(
+                    MountFlags::RDONLY
+                        | MountFlags::NOSUID
+                        | MountFlags::NODEV
+                        | MountFlags::NOEXEC
+                        | MountFlags::SYNCHRONOUS,
+                );
+            } else if option == "rbind" {
+                parsed.flags |= MountFlags::BIND | MountFlags::REC;
+            } else if let Some(&(flag, _)) = FLAG_OPTIONS.iter().find(|(_, name)| *name == option) {
+                parsed.flags |= flag;
+            } else if let Some(&(flag, _)) = CLEAR_OPTIONS.iter().find(|(_, name)| *name == option)
+            {
+                parsed.flags.remove(flag);
+            } else if let Some(propagation) = parse_propagation(option) {
+                parsed.propagation = Some(propagation);
+            } else if is_userspace(option) {
+                parsed.flags |= implied_flags(option);
+                parsed.userspace.push(option.to_owned());
+            } else {
+                if !parsed.data.is_empty() {
+                    parsed.data.push(',');
+                }
+
+                parsed.data.push_str(option);
+            }
+        }
+
+        parsed
+    }
+
+    /// Whether the userspace option `name` was given, either alone or with a value.
+    #[must_use]
+    pub fn has_userspace(&self, name: &str) -> bool {
+        self.userspace.iter().any(|option| {
+            option == name
+                || option
+                    .strip_prefix(name)
+                    .is_some_and(|value| value.starts_with('='))
+        })
+    }
+}
+
+impl Display for MountOptions {
+    /// Writes the options back as a single option string.
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.flags, fmt)?;
+
+        if !self.data.is_empty() {
+            write!(fmt, ",{}", self.data)?;
+        }
+
+        if let Some((propagation, recursive)) = self.propagation {
+            if let Some((_, name)) = PROPAGATION_OPTIONS.iter().find(|(p, _)| *p == propagation) {
+                write!(fmt, ",{}{}", if recursive { "r" } else { "" }, name)?;
+            }
+        }
+
+        for option in &self.userspace {
+            write!(fmt, ",{}", option)?;
+        }
+
+        // Keep the flags which a later option cleared from being implied again.
+        let implied = self
+            .userspace
+            .iter()
+            .fold(MountFlags::empty(), |flags, option| {
+                flags | implied_flags(option)
+            });
+
+        for (flag, name) in CLEAR_OPTIONS {
+            if implied.contains(flag) && !self.flags.contains(flag) {
+                write!(fmt, ",{}", name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The propagation type of an option such as `private` or `rshared`, and whether it is
+/// recursive.
+fn parse_propagation(option: &str) -> Option<(PropagationType, bool)> {
+    PROPAGATION_OPTIONS.iter().find_map(|&(propagation, name)| {
+        if option == name {
+            Some((propagation, false))
+        } else if option.strip_prefix('r') == Some(name) {
+            Some((propagation, true))
+        } else {
+            None
+        }
+    })
+}
+
+/// The flags implied by a userspace option such as `user`.
+fn implied_flags(option: &str) -> MountFlags {
+    IMPLYING_OPTIONS
+        .iter()
+        .find(|(name, _)| *name == option)
+        .map_or(MountFlags::empty(), |&(_, flags)| flags)
+}
+
+/// Whether an option is only meaningful to userspace, and must not be passed to the kernel.
+fn is_userspace(option: &str) -> bool {
+    let name = option.split_once('=').map_or(option, |(name, _)| name);
+
+    matches!(
+        name,
+        "auto"
+            | "noauto"
+            | "nofail"
+            | "user"
+            | "nouser"
+            | "users"
+            | "owner"
+            | "group"
+            | "comment"
+            | "_netdev"
+            | "loop"
+            | "offset"
+            | "sizelimit"
+            | "helper"
+            | "uhelper"
+    ) || name.starts_with("x-")
+        || name.starts_with("X-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_options() {
+        let options = MountOptions::parse(
+            "defaults,ro,rbind,nodev,dev,\"lowerdir=/a,/b\",mode=0755,rshared,private,x-mount.mkdir,nofail",
+        );
+
+        assert_eq!(
+            options.flags,
+            MountFlags::RDONLY | MountFlags::BIND | MountFlags::REC
+        );
+        assert_eq!(options.data, "\"lowerdir=/a,/b\",mode=0755");
+        assert_eq!(options.propagation, Some((PropagationType::PRIVATE, false)));
+        assert_eq!(options.userspace, ["x-mount.mkdir", "nofail"]);
+        assert!(options.has_userspace("x-mount.mkdir"));
+        assert!(!options.has_userspace("x-mount"));
+    }
+
+    #[test]
+    fn user_implies_flags() {
+        let options = MountOptions::parse("user");
+        let secure = MountFlags::NOEXEC | MountFlags::NOSUID | MountFlags::NODEV;
+        assert_eq!(options.flags, secure);
+
+        let options = MountOptions::parse("users,exec");
+        assert_eq!(options.flags, MountFlags::NOSUID | MountFlags::NODEV);
+
+        let options = MountOptions::parse("owner,dev");
+        assert_eq!(options.flags, MountFlags::NOSUID);
+
+        // `user=` is recorded by `mount(8)` for the user who mounted, and implies nothing.
+        assert_eq!(MountOptions::parse("user=root").flags, MountFlags::empty());
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for options in [
+            "defaults",
+            "ro,noatime,subvol=@home,compress=zstd,rslave,nofail",
+            "rbind,\"lowerdir=/a,/b\",private",
+            "user",
+            "user,exec,suid,dev",
+            "group,nodev,dev,x-gvfs-show",
+        ] {
+            let parsed = MountOptions::parse(options);
+            let written = parsed.to_string();
+            assert_eq!(
+                MountOptions::parse(&written),
+                parsed,
+                "{options} as {written}"
+            );
+        }
+
+        assert_eq!(
+            MountOptions::parse("ro,nofail,rslave").to_string(),
+            "ro,rslave,nofail"
+        );
+        assert_eq!(
+            MountOptions::parse("user,exec").to_string(),
+            "nosuid,nodev,user,exec"
+        );
+    }
+}
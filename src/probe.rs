@@ -0,0 +1,1114 @@
+// Copyright 2018-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::{
+    fs::File,
+    io::{self, Seek, SeekFrom},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+};
+
+/// The identity of the file system on a device or image, as found by [`probe`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ProbeResult {
+    /// The file system type, named as by blkid, such as `ext4` or `vfat`.
+    pub fstype: String,
+    /// The UUID of the file system, in the form used by `UUID=` in fstab.
+    pub uuid: Option<String>,
+    /// The label of the file system, in the form used by `LABEL=` in fstab.
+    pub label: Option<String>,
+    /// The block size recorded in the superblock, which is the sector size for the FAT and
+    /// NTFS families, and the page size for swap.
+    pub block_size: Option<u32>,
+    /// The size of the device or image, in bytes.
+    pub size: u64,
+}
+
+/// Reads the type, UUID, and label of the file system on a device or image file from its
+/// superblock, much like `blkid`.
+///
+/// Recognises ext2/3/4, btrfs, xfs, vfat, exfat, ntfs, f2fs, iso9660, udf, squashfs, erofs,
+/// and swap. Devices which must be opened or assembled before their contents can be mounted
+/// are reported as `crypto_LUKS`, `LVM2_member`, `linux_raid_member`, or `bcache`. The labels
+/// of exfat and ntfs, and the UUIDs and labels of udf and squashfs, are not read, and so are
+/// `None`.
+///
+/// ```rust
+/// use std::io::Write;
+///
+/// // The boot sector of a FAT16 file system.
+/// let mut sector = [0u8; 512];
+/// sector[0x0B..0x0D].copy_from_slice(&512u16.to_le_bytes());
+/// sector[0x27..0x2B].copy_from_slice(&0x1234_ABCDu32.to_le_bytes());
+/// sector[0x2B..0x36].copy_from_slice(b"INSTALLER  ");
+/// sector[0x36..0x3E].copy_from_slice(b"FAT16   ");
+/// sector[510..512].copy_from_slice(&[0x55, 0xAA]);
+///
+/// let path = std::env::temp_dir().join("sys-mount-probe.img");
+/// let mut image = std::fs::File::create(&path).unwrap();
+/// image.write_all(&sector).unwrap();
+/// image.set_len(1024 * 1024).unwrap();
+///
+/// let result = sys_mount::probe(&path).unwrap();
+/// std::fs::remove_file(&path).unwrap();
+///
+/// assert_eq!(result.fstype, "vfat");
+/// assert_eq!(result.uuid.as_deref(), Some("1234-ABCD"));
+/// assert_eq!(result.label.as_deref(), Some("INSTALLER"));
+/// assert_eq!(result.block_size, Some(512));
+/// assert_eq!(result.size, 1024 * 1024);
+/// ```
+///
+/// # Errors
+///
+/// If the path could not be opened or read, or does not hold a recognised file system.
+pub fn probe(path: impl AsRef<Path>) -> io::Result<ProbeResult> {
+    let path = path.as_ref();
+    let mut file = File::open(path)?;
+
+    let superblock = superblock(&file).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no recognised file system on {}", path.display()),
+        )
+    })?;
+
+    Ok(ProbeResult {
+        fstype: superblock.fstype.to_owned(),
+        uuid: superblock.uuid,
+        label: superblock.label,
+        block_size: superblock.block_size,
+        size: file.seek(SeekFrom::End(0))?,
+    })
+}
+
+/// The identity of a file system, as read from its superblock.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Superblock {
+    pub fstype: &'static str,
+    pub uuid: Option<String>,
+    pub label: Option<String>,
+    pub block_size: Option<u32>,
+}
+
+/// The identity of a partition, as read from the partition table of its disk.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct PartitionEntry {
+    /// The partition number, counting from 1, as in `/sys/class/block/<name>/partition`.
+    pub number: u32,
+    pub uuid: Option<String>,
+    pub label: Option<String>,
+}
+
+/// A device which holds something other than a file system, and so can not be mounted
+/// until it has been opened, assembled, or activated.
+///
+/// Returned by [`MountBuilder::mount`](crate::MountBuilder::mount) when the file system type
+/// is to be detected, and may be retrieved from the `io::Error` with `get_ref` and
+/// `downcast_ref`.
+#[derive(Debug, Error)]
+#[allow(clippy::module_name_repetitions)]
+pub enum NotMountableError {
+    /// A LUKS encrypted volume, holding a file system which is only readable once opened.
+    #[error("{0} holds a LUKS encrypted volume, which must be opened with cryptsetup first")]
+    Luks(PathBuf),
+    /// An LVM physical volume, from which logical volumes are created by activating its
+    /// volume group.
+    #[error("{0} is an LVM physical volume, whose volume group must be activated first")]
+    LvmPhysicalVolume(PathBuf),
+    /// A member of a Linux software RAID array, which is mounted through the assembled
+    /// `/dev/mdN` device.
+    #[error("{0} is a member of a Linux RAID array, which must be assembled first")]
+    RaidMember(PathBuf),
+    /// A bcache backing or caching device, which is mounted through its `/dev/bcacheN` device.
+    #[error("{0} is a bcache device, which must be registered and mounted as /dev/bcacheN")]
+    Bcache(PathBuf),
+    /// Swap space, holding no file system.
+    #[error("{0} is swap space, which is enabled with swapon rather than mounted")]
+    Swap(PathBuf),
+}
+
+impl Superblock {
+    /// The kernel file systems which may mount this, in order of preference.
+    pub fn drivers(&self) -> Vec<&'static str> {
+        match self.fstype {
+            // The ext4 driver mounts older revisions when those are not built separately.
+            "ext2" => vec!["ext2", "ext4"],
+            "ext3" => vec!["ext3", "ext4"],
+            "ntfs" => vec!["ntfs3", "ntfs"],
+            "crypto_LUKS" | "LVM2_member" | "linux_raid_member" | "bcache" | "swap" => Vec::new(),
+            fstype => vec![fstype],
+        }
+    }
+
+    /// Explains why `device` can not be mounted, if this is not a file system.
+    pub fn not_mountable(&self, device: &Path) -> Option<NotMountableError> {
+        let device = device.to_owned();
+
+        match self.fstype {
+            "crypto_LUKS" => Some(NotMountableError::Luks(device)),
+            "LVM2_member" => Some(NotMountableError::LvmPhysicalVolume(device)),
+            "linux_raid_member" => Some(NotMountableError::RaidMember(device)),
+            "bcache" => Some(NotMountableError::Bcache(device)),
+            "swap" => Some(NotMountableError::Swap(device)),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the superblock of the file system on `device`, if it is of a known type.
+pub(crate) fn superblock(device: &File) -> Option<Superblock> {
+    superblock_at(device, 0)
+}
+
+/// Reads the superblock of a file system which begins `offset` bytes into `device`.
+pub(crate) fn superblock_at(device: &File, offset: u64) -> Option<Superblock> {
+    // Containers come first, as a RAID member or bcache device may wrap a file system whose
+    // superblock remains visible. Types which place their magic further into the device are
+    // tried after those which place it earlier, so that leftovers from a previous file system
+    // are less likely to win. UDF is tried before ISO 9660, as hybrid discs carry both.
+    const PROBES: [fn(&Reader<'_>) -> Option<Superblock>; 16] = [
+        luks, lvm2, raid, bcache, xfs, squashfs, ntfs, exfat, vfat, ext, f2fs, erofs, swap, udf,
+        iso9660, btrfs,
+    ];
+
+    let reader = Reader(device, offset);
+    PROBES.iter().find_map(|probe| probe(&reader))
+}
+
+/// Reads the entries of the GPT, or primary MBR, partition table of `disk`.
+pub(crate) fn partitions(disk: &File) -> Vec<PartitionEntry> {
+    let reader = Reader(disk, 0);
+    gpt(&reader).or_else(|| mbr(&reader)).unwrap_or_default()
+}
+
+/// Reads from a device at fixed offsets from a base, treating short reads and errors as
+/// absent data.
+struct Reader<'a>(&'a File, u64);
+
+impl Reader<'_> {
+    fn bytes(&self, offset: u64, len: usize) -> Option<Vec<u8>> {
+        let mut buffer = vec![0; len];
+        self.0
+            .read_exact_at(&mut buffer, self.1.checked_add(offset)?)
+            .ok()?;
+        Some(buffer)
+    }
+
+    fn matches(&self, offset: u64, magic: &[u8]) -> bool {
+        self.bytes(offset, magic.len())
+            .is_some_and(|bytes| bytes == magic)
+    }
+
+    fn size(&self) -> Option<u64> {
+        let mut file = self.0;
+        file.seek(SeekFrom::End(0)).ok()?.checked_sub(self.1)
+    }
+}
+
+fn luks(reader: &Reader<'_>) -> Option<Superblock> {
+    let header = reader.bytes(0, 208)?;
+    if &header[0..6] != b"LUKS\xBA\xBE" {
+        return None;
+    }
+
+    // Only the second version has a label, where the first has the name of its cipher.
+    let version = u16::from_be_bytes([header[6], header[7]]);
+
+    Some(Superblock {
+        fstype: "crypto_LUKS",
+        uuid: label(&header[168..208]),
+        label: label(&header[24..72]).filter(|_| version == 2),
+        block_size: None,
+    })
+}
+
+fn lvm2(reader: &Reader<'_>) -> Option<Superblock> {
+    // The label may be written to any of the first four sectors.
+    let (sector, header) = (0..4).find_map(|sector| {
+        let header = reader.bytes(sector * 512, 64)?;
+        (&header[0..8] == b"LABELONE" && &header[24..32] == b"LVM2 001").then_some((sector, header))
+    })?;
+
+    let offset = u64::from(le32(&header, 20));
+    let id = reader.bytes(sector * 512 + offset, 32)?;
+    let id = String::from_utf8_lossy(&id);
+
+    // Physical volume IDs are grouped as 6-4-4-4-4-4-6 for display.
+    let uuid = [0..6, 6..10, 10..14, 14..18, 18..22, 22..26, 26..32]
+        .into_iter()
+        .filter_map(|range| id.get(range))
+        .collect::<Vec<_>>()
+        .join("-");
+
+    Some(Superblock {
+        fstype: "LVM2_member",
+        uuid: Some(uuid),
+        label: None,
+        block_size: None,
+    })
+}
+
+fn raid(reader: &Reader<'_>) -> Option<Superblock> {
+    const MAGIC: u32 = 0xA92B_4EFC;
+
+    let size = reader.size()?;
+
+    // Version 1.1 and 1.2 metadata is at the start, and 0.90 and 1.0 metadata at the end.
+    let offsets = [
+        Some(0),
+        Some(4096),
+        (size & !0xFFFF).checked_sub(0x10000),
+        size.checked_sub(8192).map(|offset| offset & !0xFFF),
+    ];
+
+    offsets.into_iter().flatten().find_map(|offset| {
+        let sb = reader.bytes(offset, 64)?;
+        if le32(&sb, 0) != MAGIC {
+            return None;
+        }
+
+        let (uuid, label) = match le32(&sb, 4) {
+            0 => {
+                let words = [20, 52, 56, 60].map(|offset| le32(&sb, offset).to_be_bytes());
+                (uuid(&words.concat()), None)
+            }
+            1 => (uuid(&sb[16..32]), label(&sb[32..64])),
+            _ => return None,
+        };
+
+        Some(Superblock {
+            fstype: "linux_raid_member",
+            uuid,
+            label,
+            block_size: None,
+        })
+    })
+}
+
+fn bcache(reader: &Reader<'_>) -> Option<Superblock> {
+    const MAGIC: [u8; 16] = [
+        0xC6, 0x85, 0x73, 0xF6, 0x4E, 0x1A, 0x45, 0xCA, 0x82, 0x65, 0xF5, 0x7F, 0x48, 0xBA, 0x6D,
+        0x81,
+    ];
+
+    let sb = reader.bytes(4096, 104)?;
+    if sb[24..40] != MAGIC {
+        return None;
+    }
+
+    Some(Superblock {
+        fstype: "bcache",
+        uuid: uuid(&sb[40..56]),
+        label: label(&sb[72..104]),
+        block_size: None,
+    })
+}
+
+fn ext(reader: &Reader<'_>) -> Option<Superblock> {
+    const COMPAT_HAS_JOURNAL: u32 = 0x4;
+    const INCOMPAT_EXT3: u32 = 0x2 | 0x4 | 0x10;
+    const RO_COMPAT_EXT3: u32 = 0x1 | 0x2 | 0x4;
+
+    let sb = reader.bytes(1024, 1024)?;
+    if le16(&sb, 0x38) != 0xEF53 {
+        return None;
+    }
+
+    let compat = le32(&sb, 0x5C);
+    let incompat = le32(&sb, 0x60);
+    let ro_compat = le32(&sb, 0x64);
+
+    let fstype = if incompat & !INCOMPAT_EXT3 != 0 || ro_compat & !RO_COMPAT_EXT3 != 0 {
+        "ext4"
+    } else if compat & COMPAT_HAS_JOURNAL != 0 {
+        "ext3"
+    } else {
+        "ext2"
+    };
+
+    Some(Superblock {
+        fstype,
+        uuid: uuid(&sb[0x68..0x78]),
+        label: label(&sb[0x78..0x88]),
+        block_size: 1024u32.checked_shl(le32(&sb, 0x18)),
+    })
+}
+
+fn btrfs(reader: &Reader<'_>) -> Option<Superblock> {
+    let sb = reader.bytes(0x10000, 0x22B)?;
+    if &sb[0x40..0x48] != b"_BHRfS_M" {
+        return None;
+    }
+
+    Some(Superblock {
+        fstype: "btrfs",
+        uuid: uuid(&sb[0x20..0x30]),
+        label: label(&sb[0x12B..0x22B]),
+        block_size: Some(le32(&sb, 0x90)),
+    })
+}
+
+fn xfs(reader: &Reader<'_>) -> Option<Superblock> {
+    let sb = reader.bytes(0, 120)?;
+    if &sb[0..4] != b"XFSB" {
+        return None;
+    }
+
+    Some(Superblock {
+        fstype: "xfs",
+        uuid: uuid(&sb[32..48]),
+        label: label(&sb[108..120]),
+        block_size: Some(u32::from(u16::from_be_bytes([sb[102], sb[103]]))),
+    })
+}
+
+fn vfat(reader: &Reader<'_>) -> Option<Superblock> {
+    let sector = reader.bytes(0, 512)?;
+    if sector[510..512] != [0x55, 0xAA] {
+        return None;
+    }
+
+    // FAT32 extends the BIOS parameter block, moving the serial and label further in.
+    let (serial, name) = if &sector[0x52..0x5A] == b"FAT32   " {
+        (0x43, 0x47)
+    } else if &sector[0x36..0x3A] == b"FAT1" {
+        (0x27, 0x2B)
+    } else {
+        return None;
+    };
+
+    let serial = le32(&sector, serial);
+
+    Some(Superblock {
+        fstype: "vfat",
+        uuid: Some(format!("{:04X}-{:04X}", serial >> 16, serial & 0xFFFF)),
+        label: label(&sector[name..name + 11]).filter(|label| label != "NO NAME"),
+        block_size: Some(u32::from(le16(&sector, 0x0B))),
+    })
+}
+
+fn f2fs(reader: &Reader<'_>) -> Option<Superblock> {
+    let sb = reader.bytes(1024, 0x47C)?;
+    if le32(&sb, 0) != 0xF2F5_2010 {
+        return None;
+    }
+
+    Some(Superblock {
+        fstype: "f2fs",
+        uuid: uuid(&sb[0x6C..0x7C]),
+        label: utf16_label(&sb[0x7C..0x47C]),
+        block_size: 1u32.checked_shl(le32(&sb, 0x10)),
+    })
+}
+
+fn erofs(reader: &Reader<'_>) -> Option<Superblock> {
+    let sb = reader.bytes(1024, 0x50)?;
+    if le32(&sb, 0) != 0xE0F5_E1E2 {
+        return None;
+    }
+
+    Some(Superblock {
+        fstype: "erofs",
+        uuid: uuid(&sb[0x30..0x40]),
+        label: label(&sb[0x40..0x50]),
+        block_size: 1u32.checked_shl(u32::from(sb[0x0C])),
+    })
+}
+
+fn squashfs(reader: &Reader<'_>) -> Option<Superblock> {
+    let sb = reader.bytes(0, 16)?;
+    if &sb[0..4] != b"hsqs" {
+        return None;
+    }
+
+    Some(Superblock {
+        fstype: "squashfs",
+        uuid: None,
+        label: None,
+        block_size: Some(le32(&sb, 12)),
+    })
+}
+
+fn ntfs(reader: &Reader<'_>) -> Option<Superblock> {
+    let sector = reader.bytes(0, 512)?;
+    if &sector[3..11] != b"NTFS    " {
+        return None;
+    }
+
+    // The volume label is an attribute of a file in the MFT, so only the serial is read.
+    Some(Superblock {
+        fstype: "ntfs",
+        uuid: Some(format!("{:016X}", le64(&sector, 0x48))),
+        label: None,
+        block_size: Some(u32::from(le16(&sector, 0x0B))),
+    })
+}
+
+fn exfat(reader: &Reader<'_>) -> Option<Superblock> {
+    let sector = reader.bytes(0, 512)?;
+    if &sector[3..11] != b"EXFAT   " {
+        return None;
+    }
+
+    // The volume label is an entry of the root directory, so only the serial is read.
+    let serial = le32(&sector, 0x64);
+
+    Some(Superblock {
+        fstype: "exfat",
+        uuid: Some(format!("{:04X}-{:04X}", serial >> 16, serial & 0xFFFF)),
+        label: None,
+        block_size: 1u32.checked_shl(u32::from(sector[0x6C])),
+    })
+}
+
+fn udf(reader: &Reader<'_>) -> Option<Superblock> {
+    // The volume recognition sequence begins at 32 KiB, with one descriptor per sector.
+    let sector = [2048, 4096].into_iter().find(|sector| {
+        (0..8).any(|index| {
+            let identifier = reader.bytes(32768 + index * sector + 1, 5);
+            matches!(identifier.as_deref(), Some(b"NSR02" | b"NSR03"))
+        })
+    })?;
+
+    Some(Superblock {
+        fstype: "udf",
+        uuid: None,
+        label: None,
+        block_size: u32::try_from(sector).ok(),
+    })
+}
+
+fn iso9660(reader: &Reader<'_>) -> Option<Superblock> {
+    let descriptor = reader.bytes(32768, 2048)?;
+    if &descriptor[1..6] != b"CD001" {
+        return None;
+    }
+
+    // Discs have no UUID, so the creation time is used in its place, as blkid does.
+    let created = &descriptor[813..829];
+    let uuid =
+        (created.iter().all(u8::is_ascii_digit) && created.iter().any(|&c| c != b'0')).then(|| {
+            let digits = String::from_utf8_lossy(created);
+            format!(
+                "{}-{}-{}-{}-{}-{}-{}",
+                &digits[0..4],
+                &digits[4..6],
+                &digits[6..8],
+                &digits[8..10],
+                &digits[10..12],
+                &digits[12..14],
+                &digits[14..16]
+            )
+        });
+
+    Some(Superblock {
+        fstype: "iso9660",
+        uuid,
+        label: label(&descriptor[40..72]),
+        block_size: Some(u32::from(le16(&descriptor, 128))),
+    })
+}
+
+fn swap(reader: &Reader<'_>) -> Option<Superblock> {
+    // The signature ends the first page, whose size depends on the creating system.
+    let page = [4096, 8192, 16384, 65536]
+        .into_iter()
+        .find(|&page| reader.matches(page - 10, b"SWAPSPACE2"))?;
+
+    let header = reader.bytes(1024, 48)?;
+
+    Some(Superblock {
+        fstype: "swap",
+        uuid: uuid(&header[12..28]),
+        label: label(&header[28..44]),
+        block_size: u32::try_from(page).ok(),
+    })
+}
+
+fn gpt(reader: &Reader<'_>) -> Option<Vec<PartitionEntry>> {
+    // The header follows the protective MBR in the first logical block, whose size is unknown.
+    let (block, header) = [512, 4096].into_iter().find_map(|block| {
+        let header = reader.bytes(block, 92)?;
+        (&header[0..8] == b"EFI PART").then_some((block, header))
+    })?;
+
+    let start = le64(&header, 72).checked_mul(block)?;
+    let count = u64::from(le32(&header, 80).min(1024));
+    let size = u64::from(le32(&header, 84));
+
+    // Entries are `128 << n` bytes, and the table must be within the disk, so that a corrupt
+    // header can't cause a huge allocation.
+    if !(128..=4096).contains(&size) || !size.is_power_of_two() {
+        return None;
+    }
+
+    let len = count * size;
+    if start.checked_add(len)? > reader.size()? {
+        return None;
+    }
+
+    let table = reader.bytes(start, len as usize)?;
+
+    let entries = table
+        .chunks_exact(size as usize)
+        .zip(1..)
+        .filter(|(entry, _)| entry[0..16].iter().any(|&byte| byte != 0))
+        .map(|(entry, number)| PartitionEntry {
+            number,
+            uuid: guid(&entry[16..32]),
+            label: utf16_label(&entry[56..128]),
+        })
+        .collect();
+
+    Some(entries)
+}
+
+fn mbr(reader: &Reader<'_>) -> Option<Vec<PartitionEntry>> {
+    let sector = reader.bytes(0, 512)?;
+    if sector[510..512] != [0x55, 0xAA] {
+        return None;
+    }
+
+    // Partition UUIDs are derived from the disk signature, as done by the kernel and blkid.
+    let signature = le32(&sector, 440);
+
+    let entries = sector[446..510]
+        .chunks_exact(16)
+        .zip(1..)
+        .filter(|(entry, _)| entry[4] != 0)
+        .map(|(_, number)| PartitionEntry {
+            number,
+            uuid: Some(format!("{:08x}-{:02x}", signature, number)),
+            label: None,
+        })
+        .collect();
+
+    Some(entries)
+}
+
+fn le16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn le32(bytes: &[u8], offset: usize) -> u32 {
+    let mut buffer = [0; 4];
+    buffer.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(buffer)
+}
+
+fn le64(bytes: &[u8], offset: usize) -> u64 {
+    let mut buffer = [0; 8];
+    buffer.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(buffer)
+}
+
+/// Formats a big-endian UUID, as stored by Linux file systems.
+fn uuid(bytes: &[u8]) -> Option<String> {
+    if bytes.iter().all(|&byte| byte == 0) {
+        return None;
+    }
+
+    let hex = bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    ))
+}
+
+/// Formats a GUID, whose first three fields are stored little-endian.
+fn guid(bytes: &[u8]) -> Option<String> {
+    let mut swapped = bytes.to_vec();
+    swapped[0..4].reverse();
+    swapped[4..6].reverse();
+    swapped[6..8].reverse();
+    uuid(&swapped)
+}
+
+/// Decodes a fixed-size label, which is padded with nul bytes or spaces.
+fn label(bytes: &[u8]) -> Option<String> {
+    let end = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    let label = String::from_utf8_lossy(&bytes[..end]);
+    let label = label.trim_end_matches(' ');
+    (!label.is_empty()).then(|| label.to_owned())
+}
+
+/// Decodes a fixed-size UTF-16 label, which is terminated by a nul unit.
+fn utf16_label(bytes: &[u8]) -> Option<String> {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|&unit| unit != 0)
+        .collect::<Vec<_>>();
+
+    Some(String::from_utf16_lossy(&units)).filter(|label| !label.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, io::Write};
+
+    /// Writes an image of `size` bytes with `fields` at their offsets to a temporary file,
+    /// which is unlinked once opened.
+    fn fixture(size: usize, fields: &[(usize, &[u8])]) -> File {
+        static COUNT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+        let mut image = vec![0u8; size];
+        for (offset, bytes) in fields {
+            image[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+
+        let count = COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let name = format!("sys-mount-fixture-{}-{}.img", std::process::id(), count);
+        let path = std::env::temp_dir().join(name);
+
+        fs::File::create(&path).unwrap().write_all(&image).unwrap();
+        let file = File::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        file
+    }
+
+    fn probe(size: usize, fields: &[(usize, &[u8])]) -> Superblock {
+        superblock(&fixture(size, fields)).expect("superblock was not recognised")
+    }
+
+    const UUID: [u8; 16] = [
+        0x3F, 0x25, 0x04, 0xE0, 0x4F, 0x89, 0x41, 0xD3, 0x9A, 0x0C, 0x03, 0x05, 0xE8, 0x2C, 0x33,
+        0x01,
+    ];
+    const UUID_STR: &str = "3f2504e0-4f89-41d3-9a0c-0305e82c3301";
+
+    fn ext(compat: u32, incompat: u32) -> Superblock {
+        probe(
+            8192,
+            &[
+                (1024 + 0x18, &2u32.to_le_bytes()),
+                (1024 + 0x38, &0xEF53u16.to_le_bytes()),
+                (1024 + 0x5C, &compat.to_le_bytes()),
+                (1024 + 0x60, &incompat.to_le_bytes()),
+                (1024 + 0x68, &UUID),
+                (1024 + 0x78, b"root"),
+            ],
+        )
+    }
+
+    #[test]
+    fn ext_revisions() {
+        let ext4 = ext(0x4, 0x40 | 0x2);
+        assert_eq!(ext4.fstype, "ext4");
+        assert_eq!(ext4.uuid.as_deref(), Some(UUID_STR));
+        assert_eq!(ext4.label.as_deref(), Some("root"));
+        assert_eq!(ext4.block_size, Some(4096));
+        assert_eq!(ext4.drivers(), ["ext4"]);
+
+        assert_eq!(ext(0x4, 0x2).fstype, "ext3");
+        assert_eq!(ext(0, 0x2).fstype, "ext2");
+        assert_eq!(ext(0, 0x2).drivers(), ["ext2", "ext4"]);
+    }
+
+    #[test]
+    fn btrfs() {
+        let sb = probe(
+            0x11000,
+            &[
+                (0x10020, &UUID),
+                (0x10040, b"_BHRfS_M"),
+                (0x10090, &4096u32.to_le_bytes()),
+                (0x1012B, b"pool"),
+            ],
+        );
+
+        assert_eq!(sb.fstype, "btrfs");
+        assert_eq!(sb.uuid.as_deref(), Some(UUID_STR));
+        assert_eq!(sb.label.as_deref(), Some("pool"));
+        assert_eq!(sb.block_size, Some(4096));
+    }
+
+    #[test]
+    fn xfs() {
+        let sb = probe(
+            4096,
+            &[
+                (0, b"XFSB"),
+                (32, &UUID),
+                (102, &512u16.to_be_bytes()),
+                (108, b"data"),
+            ],
+        );
+
+        assert_eq!(sb.fstype, "xfs");
+        assert_eq!(sb.uuid.as_deref(), Some(UUID_STR));
+        assert_eq!(sb.label.as_deref(), Some("data"));
+        assert_eq!(sb.block_size, Some(512));
+    }
+
+    #[test]
+    fn vfat() {
+        let sb = probe(
+            4096,
+            &[
+                (0x0B, &512u16.to_le_bytes()),
+                (0x43, &0x1234_ABCDu32.to_le_bytes()),
+                (0x47, b"EFI        "),
+                (0x52, b"FAT32   "),
+                (510, &[0x55, 0xAA]),
+            ],
+        );
+
+        assert_eq!(sb.fstype, "vfat");
+        assert_eq!(sb.uuid.as_deref(), Some("1234-ABCD"));
+        assert_eq!(sb.label.as_deref(), Some("EFI"));
+        assert_eq!(sb.block_size, Some(512));
+
+        // The placeholder label of an unlabelled volume is not reported.
+        let sb = probe(
+            4096,
+            &[
+                (0x2B, b"NO NAME    "),
+                (0x36, b"FAT16   "),
+                (510, &[0x55, 0xAA]),
+            ],
+        );
+
+        assert_eq!(sb.fstype, "vfat");
+        assert_eq!(sb.label, None);
+    }
+
+    #[test]
+    fn exfat() {
+        let sb = probe(
+            4096,
+            &[
+                (3, b"EXFAT   "),
+                (0x64, &0xDEAD_BEEFu32.to_le_bytes()),
+                (0x6C, &[9]),
+                (510, &[0x55, 0xAA]),
+            ],
+        );
+
+        assert_eq!(sb.fstype, "exfat");
+        assert_eq!(sb.uuid.as_deref(), Some("DEAD-BEEF"));
+        assert_eq!(sb.block_size, Some(512));
+    }
+
+    #[test]
+    fn ntfs() {
+        let sb = probe(
+            4096,
+            &[
+                (3, b"NTFS    "),
+                (0x0B, &512u16.to_le_bytes()),
+                (0x48, &0x1234_ABCD_5678_EF00u64.to_le_bytes()),
+                (510, &[0x55, 0xAA]),
+            ],
+        );
+
+        assert_eq!(sb.fstype, "ntfs");
+        assert_eq!(sb.uuid.as_deref(), Some("1234ABCD5678EF00"));
+        assert_eq!(sb.block_size, Some(512));
+        assert_eq!(sb.drivers(), ["ntfs3", "ntfs"]);
+    }
+
+    #[test]
+    fn f2fs() {
+        let name = "flash"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+
+        let sb = probe(
+            8192,
+            &[
+                (1024, &0xF2F5_2010u32.to_le_bytes()),
+                (1024 + 0x10, &12u32.to_le_bytes()),
+                (1024 + 0x6C, &UUID),
+                (1024 + 0x7C, &name),
+            ],
+        );
+
+        assert_eq!(sb.fstype, "f2fs");
+        assert_eq!(sb.uuid.as_deref(), Some(UUID_STR));
+        assert_eq!(sb.label.as_deref(), Some("flash"));
+        assert_eq!(sb.block_size, Some(4096));
+    }
+
+    #[test]
+    fn erofs() {
+        let sb = probe(
+            4096,
+            &[
+                (1024, &0xE0F5_E1E2u32.to_le_bytes()),
+                (1024 + 0x0C, &[12]),
+                (1024 + 0x30, &UUID),
+                (1024 + 0x40, b"image"),
+            ],
+        );
+
+        assert_eq!(sb.fstype, "erofs");
+        assert_eq!(sb.uuid.as_deref(), Some(UUID_STR));
+        assert_eq!(sb.label.as_deref(), Some("image"));
+        assert_eq!(sb.block_size, Some(4096));
+    }
+
+    #[test]
+    fn squashfs() {
+        let sb = probe(4096, &[(0, b"hsqs"), (12, &131_072u32.to_le_bytes())]);
+
+        assert_eq!(sb.fstype, "squashfs");
+        assert_eq!(sb.uuid, None);
+        assert_eq!(sb.block_size, Some(131_072));
+    }
+
+    #[test]
+    fn iso9660() {
+        let sb = probe(
+            36864,
+            &[
+                (32768, &[1]),
+                (32769, b"CD001"),
+                (32768 + 40, b"MY_DISC                         "),
+                (32768 + 128, &2048u16.to_le_bytes()),
+                (32768 + 813, b"2024010203040500"),
+            ],
+        );
+
+        assert_eq!(sb.fstype, "iso9660");
+        assert_eq!(sb.uuid.as_deref(), Some("2024-01-02-03-04-05-00"));
+        assert_eq!(sb.label.as_deref(), Some("MY_DISC"));
+        assert_eq!(sb.block_size, Some(2048));
+    }
+
+    #[test]
+    fn udf() {
+        // A hybrid disc also carries an ISO 9660 descriptor, but is reported as UDF.
+        let sb = probe(
+            40960,
+            &[
+                (32769, b"CD001"),
+                (32768 + 2048 + 1, b"BEA01"),
+                (32768 + 4096 + 1, b"NSR02"),
+            ],
+        );
+
+        assert_eq!(sb.fstype, "udf");
+        assert_eq!(sb.block_size, Some(2048));
+    }
+
+    #[test]
+    fn swap() {
+        let sb = probe(
+            8192,
+            &[
+                (1024 + 12, &UUID),
+                (1024 + 28, b"swp"),
+                (4096 - 10, b"SWAPSPACE2"),
+            ],
+        );
+
+        assert_eq!(sb.fstype, "swap");
+        assert_eq!(sb.uuid.as_deref(), Some(UUID_STR));
+        assert_eq!(sb.label.as_deref(), Some("swp"));
+        assert_eq!(sb.block_size, Some(4096));
+        assert!(sb.drivers().is_empty());
+        assert!(matches!(
+            sb.not_mountable(Path::new("/dev/sda2")),
+            Some(NotMountableError::Swap(_))
+        ));
+    }
+
+    #[test]
+    fn luks() {
+        let sb = probe(
+            4096,
+            &[
+                (0, b"LUKS\xBA\xBE"),
+                (6, &2u16.to_be_bytes()),
+                (24, b"vault"),
+                (168, UUID_STR.as_bytes()),
+            ],
+        );
+
+        assert_eq!(sb.fstype, "crypto_LUKS");
+        assert_eq!(sb.uuid.as_deref(), Some(UUID_STR));
+        assert_eq!(sb.label.as_deref(), Some("vault"));
+        assert!(matches!(
+            sb.not_mountable(Path::new("/dev/sda3")),
+            Some(NotMountableError::Luks(_))
+        ));
+    }
+
+    #[test]
+    fn lvm2() {
+        let sb = probe(
+            4096,
+            &[
+                (512, b"LABELONE"),
+                (512 + 20, &32u32.to_le_bytes()),
+                (512 + 24, b"LVM2 001"),
+                (512 + 32, b"AbCdEf1234567890GhIjKlMnOpQrStUv"),
+            ],
+        );
+
+        assert_eq!(sb.fstype, "LVM2_member");
+        assert_eq!(
+            sb.uuid.as_deref(),
+            Some("AbCdEf-1234-5678-90Gh-IjKl-MnOp-QrStUv")
+        );
+    }
+
+    #[test]
+    fn raid() {
+        // Version 1.2 metadata, 4 KiB from the start.
+        let sb = probe(
+            65536,
+            &[
+                (4096, &0xA92B_4EFCu32.to_le_bytes()),
+                (4096 + 4, &1u32.to_le_bytes()),
+                (4096 + 16, &UUID),
+                (4096 + 32, b"host:md0"),
+            ],
+        );
+
+        assert_eq!(sb.fstype, "linux_raid_member");
+        assert_eq!(sb.uuid.as_deref(), Some(UUID_STR));
+        assert_eq!(sb.label.as_deref(), Some("host:md0"));
+
+        // Version 0.90 metadata, in the last 64 KiB-aligned block but one, in front of a
+        // file system which remains visible at the start of the member.
+        let size = 256 * 1024;
+        let sb = probe(
+            size,
+            &[
+                (1024 + 0x38, &0xEF53u16.to_le_bytes()),
+                (size - 65536, &0xA92B_4EFCu32.to_le_bytes()),
+            ],
+        );
+
+        assert_eq!(sb.fstype, "linux_raid_member");
+    }
+
+    #[test]
+    fn bcache() {
+        let magic = [
+            0xC6, 0x85, 0x73, 0xF6, 0x4E, 0x1A, 0x45, 0xCA, 0x82, 0x65, 0xF5, 0x7F, 0x48, 0xBA,
+            0x6D, 0x81,
+        ];
+
+        let sb = probe(
+            8192,
+            &[(4096 + 24, &magic), (4096 + 40, &UUID), (4096 + 72, b"bc0")],
+        );
+
+        assert_eq!(sb.fstype, "bcache");
+        assert_eq!(sb.uuid.as_deref(), Some(UUID_STR));
+        assert_eq!(sb.label.as_deref(), Some("bc0"));
+    }
+
+    #[test]
+    fn unrecognised() {
+        assert_eq!(superblock(&fixture(65536, &[])), None);
+    }
+
+    #[test]
+    fn gpt() {
+        let name = "Root Part"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+
+        // The GUID aabbccdd-eeff-0011-2233-445566778899, with its first fields little-endian.
+        let guid = [
+            0xDD, 0xCC, 0xBB, 0xAA, 0xFF, 0xEE, 0x11, 0x00, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77,
+            0x88, 0x99,
+        ];
+
+        // The second entry is unused, and the third is numbered after it.
+        let disk = fixture(
+            65536,
+            &[
+                (510, &[0x55, 0xAA]),
+                (512, b"EFI PART"),
+                (512 + 72, &2u64.to_le_bytes()),
+                (512 + 80, &4u32.to_le_bytes()),
+                (512 + 84, &128u32.to_le_bytes()),
+                (1024, &[1; 16]),
+                (1024 + 16, &guid),
+                (1024 + 56, &name),
+                (1024 + 256, &[1; 16]),
+                (1024 + 256 + 16, &UUID),
+            ],
+        );
+
+        let partitions = partitions(&disk);
+        assert_eq!(partitions.len(), 2);
+
+        assert_eq!(partitions[0].number, 1);
+        assert_eq!(
+            partitions[0].uuid.as_deref(),
+            Some("aabbccdd-eeff-0011-2233-445566778899")
+        );
+        assert_eq!(partitions[0].label.as_deref(), Some("Root Part"));
+
+        assert_eq!(partitions[1].number, 3);
+        assert_eq!(partitions[1].label, None);
+    }
+
+    #[test]
+    fn gpt_entry_size() {
+        let header = |size: u32| {
+            fixture(
+                65536,
+                &[
+                    (510, &[0x55, 0xAA]),
+                    (512, b"EFI PART"),
+                    (512 + 72, &2u64.to_le_bytes()),
+                    (512 + 80, &128u32.to_le_bytes()),
+                    (512 + 84, &size.to_le_bytes()),
+                    (1024, &[1; 16]),
+                ],
+            )
+        };
+
+        // A table which would need terabytes, isn't a multiple of 128 bytes, or runs past the
+        // end of the disk is not read.
+        for size in [u32::MAX, 1 << 31, 200, 4096] {
+            assert!(partitions(&header(size)).is_empty());
+        }
+
+        assert_eq!(partitions(&header(256)).len(), 1);
+    }
+
+    #[test]
+    fn mbr() {
+        let disk = fixture(
+            4096,
+            &[
+                (440, &0x1A2B_3C4Du32.to_le_bytes()),
+                (446 + 4, &[0x83]),
+                (446 + 16 * 2 + 4, &[0x82]),
+                (510, &[0x55, 0xAA]),
+            ],
+        );
+
+        let partitions = partitions(&disk);
+        let uuids = partitions
+            .iter()
+            .map(|partition| (partition.number, partition.uuid.as_deref().unwrap()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(uuids, [(1, "1a2b3c4d-01"), (3, "1a2b3c4d-03")]);
+    }
+}
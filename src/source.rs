@@ -0,0 +1,308 @@
+// Copyright 2018-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::probe::{self, PartitionEntry};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// A source which names a device by the identity of its file system or partition, as
+/// written in the first field of `/etc/fstab`.
+///
+/// ```rust
+/// use sys_mount::SourceTag;
+///
+/// let tag = SourceTag::parse("LABEL=\"Backup Drive\"").unwrap();
+/// assert_eq!(tag, SourceTag::Label("Backup Drive".into()));
+/// assert_eq!(tag.to_string(), "LABEL=Backup Drive");
+///
+/// assert_eq!(SourceTag::parse("/dev/sda1"), None);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SourceTag {
+    /// `UUID=`, the UUID of a file system.
+    Uuid(String),
+    /// `LABEL=`, the label of a file system.
+    Label(String),
+    /// `PARTUUID=`, the unique GUID of a GPT partition, or the MBR disk signature followed by
+    /// the partition number.
+    PartUuid(String),
+    /// `PARTLABEL=`, the name of a GPT partition.
+    PartLabel(String),
+}
+
+/// Why a [`SourceTag`] could not be resolved to a device, which may be retrieved from the
+/// returned `io::Error` with `get_ref` and `downcast_ref`.
+#[derive(Debug, Error)]
+pub enum SourceTagError {
+    /// No device has the tag.
+    #[error("no device was found with {0}")]
+    NotFound(SourceTag),
+    /// More than one device has the tag.
+    #[error("{tag} is ambiguous, as it matches each of {devices:?}")]
+    Ambiguous {
+        /// The tag which was being resolved.
+        tag: SourceTag,
+        /// Every device with the tag, sorted by path.
+        devices: Vec<PathBuf>,
+    },
+}
+
+impl SourceTag {
+    /// Parses a tag such as `UUID=…`, returning `None` if `source` is not a tag. The value
+    /// may be quoted, as permitted in fstab.
+    #[must_use]
+    pub fn parse(source: &str) -> Option<Self> {
+        let (tag, value) = source.split_once('=')?;
+
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value)
+            .to_owned();
+
+        if value.is_empty() {
+            return None;
+        }
+
+        match tag {
+            "UUID" => Some(SourceTag::Uuid(value)),
+            "LABEL" => Some(SourceTag::Label(value)),
+            "PARTUUID" => Some(SourceTag::PartUuid(value)),
+            "PARTLABEL" => Some(SourceTag::PartLabel(value)),
+            _ => None,
+        }
+    }
+
+    /// The value of the tag, without its name.
+    #[must_use]
+    pub fn value(&self) -> &str {
+        match self {
+            SourceTag::Uuid(value)
+            | SourceTag::Label(value)
+            | SourceTag::PartUuid(value)
+            | SourceTag::PartLabel(value) => value,
+        }
+    }
+
+    /// Finds the device with this tag.
+    ///
+    /// The symbolic links which udev maintains in `/dev/disk/by-*` are consulted first.
+    /// Where those are missing, such as in an initramfs or a minimal container, every block
+    /// device in `/sys/class/block` is scanned by reading its superblock, or the partition
+    /// table of its disk. UUIDs are compared case-insensitively.
+    ///
+    /// As udev links a tag to only one device, a UUID, PARTUUID, or PARTLABEL shared by
+    /// several devices, such as those of a cloned disk, resolves to whichever device udev
+    /// chose rather than being reported as ambiguous. Labels are commonly shared, so they
+    /// are always scanned for, and their link is only followed if the scan finds nothing,
+    /// such as when the caller may not read the devices.
+    ///
+    /// ```no_run
+    /// use sys_mount::SourceTag;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    ///     let tag = SourceTag::parse("PARTLABEL=EFI System Partition").unwrap();
+    ///     println!("{} is {}", tag, tag.resolve()?.display());
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// With a [`SourceTagError`] if no device has the tag, or if the scan found more than one
+    /// device with it. Otherwise if the block devices could not be listed.
+    pub fn resolve(&self) -> io::Result<PathBuf> {
+        let label = matches!(self, SourceTag::Label(_));
+
+        if !label {
+            if let Some(device) = self.resolve_link() {
+                return Ok(device);
+            }
+        }
+
+        let mut devices = self.scan()?;
+
+        if label && devices.is_empty() {
+            devices.extend(self.resolve_link());
+        }
+
+        match devices.len() {
+            1 => Ok(devices.remove(0)),
+            0 => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                SourceTagError::NotFound(self.clone()),
+            )),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                SourceTagError::Ambiguous {
+                    tag: self.clone(),
+                    devices,
+                },
+            )),
+        }
+    }
+
+    /// Follows the udev link for this tag, if there is one.
+    fn resolve_link(&self) -> Option<PathBuf> {
+        let directory = match self {
+            SourceTag::Uuid(_) => "/dev/disk/by-uuid",
+            SourceTag::Label(_) => "/dev/disk/by-label",
+            SourceTag::PartUuid(_) => "/dev/disk/by-partuuid",
+            SourceTag::PartLabel(_) => "/dev/disk/by-partlabel",
+        };
+
+        let value = self.value();
+        let lowercase = value.to_ascii_lowercase();
+
+        let link = |name: &str| fs::canonicalize(Path::new(directory).join(udev_escape(name)));
+        link(value).or_else(|_| link(&lowercase)).ok()
+    }
+
+    /// Scans every block device for this tag.
+    fn scan(&self) -> io::Result<Vec<PathBuf>> {
+        let mut tables: HashMap<String, Vec<PartitionEntry>> = HashMap::new();
+        let mut devices = Vec::new();
+
+        for entry in fs::read_dir("/sys/class/block")? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let device = Path::new("/dev").join(&name);
+
+            let found = match self {
+                SourceTag::Uuid(_) | SourceTag::Label(_) => {
+                    let Ok(file) = File::open(&device) else {
+                        continue;
+                    };
+
+                    probe::superblock(&file).is_some_and(|superblock| match self {
+                        SourceTag::Uuid(uuid) => superblock
+                            .uuid
+                            .is_some_and(|found| found.eq_ignore_ascii_case(uuid)),
+                        _ => superblock.label.as_deref() == Some(self.value()),
+                    })
+                }
+
+                SourceTag::PartUuid(_) | SourceTag::PartLabel(_) => {
+                    let Some((disk, number)) = partition_of(&entry.path()) else {
+                        continue;
+                    };
+
+                    let table = tables.entry(disk).or_insert_with_key(|disk| {
+                        File::open(Path::new("/dev").join(disk))
+                            .map(|file| probe::partitions(&file))
+                            .unwrap_or_default()
+                    });
+
+                    table
+                        .iter()
+                        .find(|partition| partition.number == number)
+                        .is_some_and(|partition| match self {
+                            SourceTag::PartUuid(uuid) => partition
+                                .uuid
+                                .as_ref()
+                                .is_some_and(|found| found.eq_ignore_ascii_case(uuid)),
+                            _ => partition.label.as_deref() == Some(self.value()),
+                        })
+                }
+            };
+
+            if found {
+                devices.push(device);
+            }
+        }
+
+        devices.sort();
+        Ok(devices)
+    }
+}
+
+impl Display for SourceTag {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        let tag = match self {
+            SourceTag::Uuid(_) => "UUID",
+            SourceTag::Label(_) => "LABEL",
+            SourceTag::PartUuid(_) => "PARTUUID",
+            SourceTag::PartLabel(_) => "PARTLABEL",
+        };
+
+        write!(fmt, "{}={}", tag, self.value())
+    }
+}
+
+/// The name of the disk containing the partition at `sys`, and its partition number.
+fn partition_of(sys: &Path) -> Option<(String, u32)> {
+    let number = fs::read_to_string(sys.join("partition")).ok()?;
+    let number = number.trim().parse::<u32>().ok()?;
+
+    // Partitions are nested within the sysfs directory of their disk.
+    let disk = fs::canonicalize(sys).ok()?;
+    let disk = disk.parent()?.file_name()?.to_string_lossy().into_owned();
+
+    Some((disk, number))
+}
+
+/// Encodes a value as udev does for the names of its links, escaping anything but
+/// alphanumerics, UTF-8, and `#+-.:=@_` as `\xNN`.
+fn udev_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for character in value.chars() {
+        if character.is_ascii_alphanumeric()
+            || !character.is_ascii()
+            || "#+-.:=@_".contains(character)
+        {
+            escaped.push(character);
+        } else {
+            escaped.push_str(&format!("\\x{:02x}", character as u32));
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_tags() {
+        let parse = SourceTag::parse;
+
+        assert_eq!(
+            parse("UUID=1234-ABCD"),
+            Some(SourceTag::Uuid("1234-ABCD".into()))
+        );
+        assert_eq!(parse("LABEL=root"), Some(SourceTag::Label("root".into())));
+        assert_eq!(
+            parse("PARTUUID=0a1b-02"),
+            Some(SourceTag::PartUuid("0a1b-02".into()))
+        );
+        assert_eq!(
+            parse("PARTLABEL=EFI"),
+            Some(SourceTag::PartLabel("EFI".into()))
+        );
+
+        assert_eq!(
+            parse("LABEL=\"Backup Drive\""),
+            Some(SourceTag::Label("Backup Drive".into()))
+        );
+
+        assert_eq!(parse("ID=usb-0001"), None);
+        assert_eq!(parse("UUID="), None);
+        assert_eq!(parse("UUID=\"\""), None);
+        assert_eq!(parse("/dev/sda1"), None);
+    }
+
+    #[test]
+    fn udev_escapes() {
+        assert_eq!(udev_escape("root"), "root");
+        assert_eq!(udev_escape("My Disk/2024"), "My\\x20Disk\\x2f2024");
+        assert_eq!(udev_escape("a#b+c-d.e:f=g@h_i"), "a#b+c-d.e:f=g@h_i");
+        assert_eq!(udev_escape("données"), "données");
+    }
+}
@@ -67,7 +67,7 @@ impl SupportedFilesystems {
 
     /// Iterate through file systems which are not associated with physical devices.
     #[must_use]
-    pub fn nodev_file_systems<'a>(&'a self) -> Box<dyn Iterator<Item = &str> + 'a> {
+    pub fn nodev_file_systems<'a>(&'a self) -> Box<dyn Iterator<Item = &'a str> + 'a> {
         // TODO: When we can, switch to `impl Iterator`.
         let iter = self.nodev.iter().enumerate().filter_map(move |(id, &x)| {
             if x {
@@ -82,7 +82,7 @@ impl SupportedFilesystems {
 
     /// Iterate through file systems which are associated with physical devices.
     #[must_use]
-    pub fn dev_file_systems<'a>(&'a self) -> Box<dyn Iterator<Item = &str> + 'a> {
+    pub fn dev_file_systems<'a>(&'a self) -> Box<dyn Iterator<Item = &'a str> + 'a> {
         // TODO: When we can, switch to `impl Iterator`.
         let iter = self.nodev.iter().enumerate().filter_map(move |(id, &x)| {
             if x {
@@ -0,0 +1,789 @@
+// Copyright 2018-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::mount::mount_id_of;
+use crate::namespace::with_child;
+use crate::{to_cstring, MountAttr, MountEvent, MountInfo, Propagation};
+use libc::{
+    c_long, c_void, syscall, SYS_pidfd_send_signal, CLONE_NEWNS, EOVERFLOW, ESRCH, MS_SHARED,
+    MS_SLAVE, MS_UNBINDABLE,
+};
+use std::{
+    collections::HashMap,
+    ffi::{CStr, OsStr},
+    fs, io,
+    ops::Deref,
+    os::{
+        fd::{AsRawFd, BorrowedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+    ptr, slice,
+    sync::OnceLock,
+};
+
+// Not yet exported by `libc`. These follow the common system call table, which MIPS offsets
+// by a base for each ABI. Alpha also differs, but has no Rust target.
+#[cfg(target_arch = "mips")]
+const SYSCALL_BASE: c_long = 4000;
+#[cfg(all(target_arch = "mips64", target_pointer_width = "64"))]
+const SYSCALL_BASE: c_long = 5000;
+#[cfg(all(target_arch = "mips64", target_pointer_width = "32"))]
+const SYSCALL_BASE: c_long = 6000;
+#[cfg(not(any(target_arch = "mips", target_arch = "mips64")))]
+const SYSCALL_BASE: c_long = 0;
+
+const SYS_STATMOUNT: c_long = SYSCALL_BASE + 457;
+const SYS_LISTMOUNT: c_long = SYSCALL_BASE + 458;
+
+const MNT_ID_REQ_SIZE_VER0: u32 = 24;
+const LSMT_ROOT: u64 = u64::MAX;
+
+const STATMOUNT_SB_BASIC: u64 = 0x0001;
+const STATMOUNT_MNT_BASIC: u64 = 0x0002;
+const STATMOUNT_PROPAGATE_FROM: u64 = 0x0004;
+const STATMOUNT_MNT_ROOT: u64 = 0x0008;
+const STATMOUNT_MNT_POINT: u64 = 0x0010;
+const STATMOUNT_FS_TYPE: u64 = 0x0020;
+const STATMOUNT_MNT_OPTS: u64 = 0x0080;
+const STATMOUNT_SB_SOURCE: u64 = 0x0200;
+
+const SB_RDONLY: u32 = 1;
+const SB_SYNCHRONOUS: u32 = 1 << 4;
+const SB_MANDLOCK: u32 = 1 << 6;
+const SB_DIRSYNC: u32 = 1 << 7;
+const SB_LAZYTIME: u32 = 1 << 25;
+
+/// Offset of the string table following the fixed-size fields of `struct statmount`.
+const STATMOUNT_STRINGS: usize = 512;
+
+#[repr(C)]
+struct MntIdReq {
+    size: u32,
+    spare: u32,
+    mnt_id: u64,
+    param: u64,
+}
+
+/// The leading fixed-size fields of `struct statmount`, mirroring the kernel's layout.
+#[repr(C)]
+#[allow(dead_code)]
+struct StatMount {
+    size: u32,
+    mnt_opts: u32,
+    mask: u64,
+    sb_dev_major: u32,
+    sb_dev_minor: u32,
+    sb_magic: u64,
+    sb_flags: u32,
+    fs_type: u32,
+    mnt_id: u64,
+    mnt_parent_id: u64,
+    mnt_id_old: u32,
+    mnt_parent_id_old: u32,
+    mnt_attr: u64,
+    mnt_propagation: u64,
+    mnt_peer_group: u64,
+    mnt_master: u64,
+    propagate_from: u64,
+    mnt_root: u32,
+    mnt_point: u32,
+    mnt_ns_id: u64,
+    fs_subtype: u32,
+    sb_source: u32,
+}
+
+/// The mounts visible to this process.
+///
+/// ```no_run
+/// use sys_mount::*;
+///
+/// fn main() -> std::io::Result<()> {
+///     let mount = Mount::builder().fstype("tmpfs").mount("tmpfs", "/tmp/location")?;
+///     let table = MountTable::list()?;
+///
+///     for info in table.iter() {
+///         println!("{} on {}", info.source, info.mount_point.display());
+///     }
+///
+///     let info = mount.mount_id().and_then(|id| table.get(id));
+///     assert_eq!(info.map(|info| info.fstype.as_str()), Some("tmpfs"));
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct MountTable {
+    mounts: Vec<MountInfo>,
+    unique_ids: bool,
+}
+
+impl MountTable {
+    /// Lists every mount in the current mount namespace.
+    ///
+    /// On Linux 6.8 and later, the mounts are enumerated with `listmount` and described with
+    /// `statmount`, and identified by their unique 64-bit mount IDs. On older kernels, or
+    /// where those calls are blocked by a seccomp filter, the table is instead parsed from
+    /// `/proc/self/mountinfo`.
+    ///
+    /// # Errors
+    ///
+    /// If the mount table could not be read.
+    pub fn list() -> io::Result<Self> {
+        if !unique_ids() {
+            return Self::from_mountinfo();
+        }
+
+        match list_with_statmount() {
+            Err(why) if matches!(why.raw_os_error(), Some(libc::ENOSYS | libc::EPERM)) => {
+                Self::from_mountinfo()
+            }
+            result => result.map(|mounts| MountTable {
+                mounts,
+                unique_ids: true,
+            }),
+        }
+    }
+
+    /// Captures the mount table as it is now, to later be compared with [`MountTable::diff`].
+    ///
+    /// This lists the table exactly as [`MountTable::list`] does.
+    ///
+    /// ```no_run
+    /// use sys_mount::*;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    ///     let before = MountTable::snapshot()?;
+    ///
+    ///     {
+    ///         let _mount = Mount::builder()
+    ///             .fstype("tmpfs")
+    ///             .mount("tmpfs", "/tmp/location")?
+    ///             .into_unmount_drop(UnmountFlags::DETACH);
+    ///     }
+    ///
+    ///     let diff = MountTable::diff(&before, &MountTable::snapshot()?);
+    ///     assert!(diff.is_empty(), "leaked mounts: {:?}", diff);
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the mount table could not be read.
+    pub fn snapshot() -> io::Result<Self> {
+        Self::list()
+    }
+
+    /// Compares two snapshots of the mount table, matching mounts by their mount IDs.
+    ///
+    /// A mount whose ID was reused by a mount elsewhere, or which was moved, is reported as
+    /// removed and added again.
+    #[must_use]
+    pub fn diff(before: &MountTable, after: &MountTable) -> MountDiff {
+        let mut diff = MountDiff::default();
+
+        for event in crate::watch::diff(before, after) {
+            match event {
+                MountEvent::Mounted(info) => diff.added.push(info),
+                MountEvent::Unmounted(info) => diff.removed.push(info),
+                MountEvent::Remounted(old, new) => diff.changed.push((old, new)),
+            }
+        }
+
+        diff
+    }
+
+    /// Parses the mount table of this process from `/proc/self/mountinfo`.
+    ///
+    /// Mounts are identified by the reusable mount IDs of mountinfo, rather than the unique
+    /// IDs that [`MountTable::list`] provides on newer kernels.
+    ///
+    /// # Errors
+    ///
+    /// If `/proc/self/mountinfo` could not be read or parsed.
+    pub fn from_mountinfo() -> io::Result<Self> {
+        read_mountinfo("/proc/self/mountinfo").map(|mounts| MountTable {
+            mounts,
+            unique_ids: false,
+        })
+    }
+
+    /// Parses the mount table of the process `pid` from `/proc/<pid>/mountinfo`, as seen from
+    /// within its mount namespace and root directory.
+    ///
+    /// # Errors
+    ///
+    /// If the process does not exist, or its mount table could not be read or parsed.
+    pub fn of_pid(pid: u32) -> io::Result<Self> {
+        read_mountinfo(&format!("/proc/{}/mountinfo", pid)).map(|mounts| MountTable {
+            mounts,
+            unique_ids: false,
+        })
+    }
+
+    /// Parses the mount table of the process referred to by `pidfd`, like
+    /// [`MountTable::of_pid`].
+    ///
+    /// Unlike a PID, a pidfd cannot be reused by another process, so the table is only
+    /// returned if the process is still alive after it was read.
+    ///
+    /// # Errors
+    ///
+    /// - If `pidfd` is not a pidfd, or the process has exited
+    /// - If its mount table could not be read or parsed
+    pub fn of_pidfd(pidfd: BorrowedFd<'_>) -> io::Result<Self> {
+        let fdinfo = fs::read_to_string(format!("/proc/self/fdinfo/{}", pidfd.as_raw_fd()))?;
+
+        let pid = fdinfo
+            .lines()
+            .find_map(|line| line.strip_prefix("Pid:"))
+            .and_then(|pid| pid.trim().parse::<i32>().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a pidfd"))?;
+
+        // A PID of -1 means the process has exited, and 0 that it is not in our namespace.
+        let pid = u32::try_from(pid)
+            .ok()
+            .filter(|&pid| pid != 0)
+            .ok_or_else(|| io::Error::from_raw_os_error(ESRCH))?;
+
+        let table = Self::of_pid(pid)?;
+
+        let result = unsafe {
+            syscall(
+                SYS_pidfd_send_signal,
+                pidfd.as_raw_fd(),
+                0,
+                ptr::null::<c_void>(),
+                0,
+            )
+        };
+
+        match result {
+            0 => Ok(table),
+            _err => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Parses the mount table as seen from within the mount namespace referred to by
+    /// `namespace`, such as an open `/proc/<pid>/ns/mnt` file.
+    ///
+    /// A child process is forked to enter the namespace just long enough for its mount table
+    /// to be read, so this requires `CAP_SYS_ADMIN` and `CAP_SYS_CHROOT` in the namespace.
+    ///
+    /// ```no_run
+    /// use std::{fs::File, os::fd::AsFd};
+    /// use sys_mount::*;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    ///     let namespace = File::open("/run/container/ns/mnt")?;
+    ///
+    ///     for info in &MountTable::of_namespace(namespace.as_fd())? {
+    ///         println!("{} on {}", info.source, info.mount_point.display());
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - If the child process could not be created, or could not enter the namespace
+    /// - If the mount table could not be read or parsed
+    pub fn of_namespace(namespace: BorrowedFd<'_>) -> io::Result<Self> {
+        with_child(
+            || unsafe { libc::setns(namespace.as_raw_fd(), CLONE_NEWNS) == 0 },
+            |pid| read_mountinfo(&format!("/proc/{}/mountinfo", pid)),
+            || {
+                io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "failed to enter mount namespace",
+                )
+            },
+        )
+        .map(|mounts| MountTable {
+            mounts,
+            unique_ids: false,
+        })
+    }
+
+    /// Finds the mount with the given ID, such as that of [`Mount::mount_id`](crate::Mount::mount_id).
+    #[must_use]
+    pub fn get(&self, mount_id: u64) -> Option<&MountInfo> {
+        self.mounts.iter().find(|info| info.mount_id == mount_id)
+    }
+
+    /// Finds the mount containing `path`, along with the mounts it propagates events to and
+    /// from.
+    ///
+    /// ```no_run
+    /// use sys_mount::*;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    ///     let info = MountTable::list()?.propagation_of("/run/container/rootfs")?;
+    ///
+    ///     // Mounts beneath a shared mount will propagate back to its peers.
+    ///     for peer in &info.peers {
+    ///         println!("shares events with {}", peer.mount_point.display());
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - If the path is not a valid C string, or could not be accessed
+    /// - If the mount containing the path is not in this table
+    pub fn propagation_of(&self, path: impl AsRef<Path>) -> io::Result<PropagationInfo> {
+        let c_path = to_cstring(path.as_ref().as_os_str().as_bytes())?;
+        let mount_id = mount_id_of(&c_path, self.unique_ids)?;
+
+        let mount = self.get(mount_id).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("mount {} is not in the mount table", mount_id),
+            )
+        })?;
+
+        let propagation = mount.propagation();
+
+        // Collects the other mounts whose selected peer group is `group`.
+        let members = |group: Option<u64>, select: fn(&Propagation) -> Option<u64>| {
+            let Some(group) = group else {
+                return Vec::new();
+            };
+
+            self.mounts
+                .iter()
+                .filter(|info| info.mount_id != mount_id)
+                .filter(|info| select(&info.propagation()) == Some(group))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        let peers = members(propagation.shared, |other| other.shared);
+        let masters = members(propagation.master, |other| other.shared);
+        let slaves = members(propagation.shared, |other| other.master);
+
+        Ok(PropagationInfo {
+            mount,
+            propagation,
+            peers,
+            masters,
+            slaves,
+        })
+    }
+
+    /// The mounts in the table, for keeping it up to date as mounts come and go.
+    pub(crate) fn mounts_mut(&mut self) -> &mut Vec<MountInfo> {
+        &mut self.mounts
+    }
+
+    /// Iterates over the mounts in the table.
+    pub fn iter(&self) -> slice::Iter<'_, MountInfo> {
+        self.mounts.iter()
+    }
+}
+
+/// The differences between two snapshots of the mount table, as found by
+/// [`MountTable::diff`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct MountDiff {
+    /// Mounts which are only in the later snapshot.
+    pub added: Vec<MountInfo>,
+    /// Mounts which are only in the earlier snapshot, deepest first.
+    pub removed: Vec<MountInfo>,
+    /// Mounts whose options or propagation differ, before and after.
+    pub changed: Vec<(MountInfo, MountInfo)>,
+}
+
+impl MountDiff {
+    /// Whether the snapshots are identical.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// The propagation relationships of a mount, as found by [`MountTable::propagation_of`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PropagationInfo {
+    /// The mount which was queried.
+    pub mount: MountInfo,
+    /// The peer groups of the mount.
+    pub propagation: Propagation,
+    /// Other mounts in the same peer group, to and from which events propagate.
+    pub peers: Vec<MountInfo>,
+    /// Mounts in the peer group which the mount receives events from.
+    pub masters: Vec<MountInfo>,
+    /// Mounts which receive events from the mount's peer group.
+    pub slaves: Vec<MountInfo>,
+}
+
+impl Deref for MountTable {
+    type Target = [MountInfo];
+
+    fn deref(&self) -> &[MountInfo] {
+        &self.mounts
+    }
+}
+
+impl IntoIterator for MountTable {
+    type Item = MountInfo;
+    type IntoIter = std::vec::IntoIter<MountInfo>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.mounts.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a MountTable {
+    type Item = &'a MountInfo;
+    type IntoIter = slice::Iter<'a, MountInfo>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.mounts.iter()
+    }
+}
+
+pub(crate) fn read_mountinfo(path: &str) -> io::Result<Vec<MountInfo>> {
+    fs::read_to_string(path)?
+        .lines()
+        .map(MountInfo::parse_line)
+        .collect()
+}
+
+/// Whether mounts are listed with `listmount` and `statmount`, and so identified by their
+/// unique mount IDs.
+///
+/// This is checked once for the process, so that the IDs of [`Mount`](crate::Mount) and
+/// [`MountWatcher`](crate::MountWatcher) are always of the same kind as those of
+/// [`MountTable::list`].
+pub(crate) fn unique_ids() -> bool {
+    static UNIQUE_IDS: OnceLock<bool> = OnceLock::new();
+
+    #[cfg(test)]
+    if tests::FORCE_MOUNTINFO.with(std::cell::Cell::get) {
+        return false;
+    }
+
+    *UNIQUE_IDS.get_or_init(|| {
+        let result = listmount().and_then(|ids| match ids.first() {
+            Some(&mount_id) => statmount(mount_id).map(drop),
+            None => Ok(()),
+        });
+
+        !matches!(
+            result.map_err(|why| why.raw_os_error()),
+            Err(Some(libc::ENOSYS | libc::EPERM))
+        )
+    })
+}
+
+fn list_with_statmount() -> io::Result<Vec<MountInfo>> {
+    let mut mounts = Vec::new();
+    let mut mountinfo = None;
+
+    for mount_id in listmount()? {
+        let (mut info, mount_id_old, complete) = match statmount(mount_id) {
+            Ok(stat) => stat,
+            // The mount was unmounted since it was listed.
+            Err(why) if why.kind() == io::ErrorKind::NotFound => continue,
+            Err(why) => return Err(why),
+        };
+
+        // Kernels prior to 6.15 cannot report the source or file system options, so take
+        // them from mountinfo instead.
+        if !complete {
+            let mountinfo = match mountinfo {
+                Some(ref mountinfo) => mountinfo,
+                None => mountinfo.insert(mountinfo_by_id()?),
+            };
+
+            if let Some(entry) = mountinfo.get(&u64::from(mount_id_old)) {
+                info.source.clone_from(&entry.source);
+                info.super_options.clone_from(&entry.super_options);
+            }
+        }
+
+        mounts.push(info);
+    }
+
+    Ok(mounts)
+}
+
+/// Describes a single mount, identified by its unique mount ID, with `statmount`.
+pub(crate) fn describe(mount_id: u64) -> io::Result<MountInfo> {
+    let (mut info, mount_id_old, complete) = statmount(mount_id)?;
+
+    if !complete {
+        if let Some(entry) = mountinfo_by_id()?.get(&u64::from(mount_id_old)) {
+            info.source.clone_from(&entry.source);
+            info.super_options.clone_from(&entry.super_options);
+        }
+    }
+
+    Ok(info)
+}
+
+fn mountinfo_by_id() -> io::Result<HashMap<u64, MountInfo>> {
+    Ok(read_mountinfo("/proc/self/mountinfo")?
+        .into_iter()
+        .map(|info| (info.mount_id, info))
+        .collect())
+}
+
+fn listmount() -> io::Result<Vec<u64>> {
+    let mut ids = Vec::new();
+    let mut buffer = [0u64; 512];
+
+    loop {
+        let request = MntIdReq {
+            size: MNT_ID_REQ_SIZE_VER0,
+            spare: 0,
+            mnt_id: LSMT_ROOT,
+            param: ids.last().copied().unwrap_or(0),
+        };
+
+        let count = unsafe {
+            syscall(
+                SYS_LISTMOUNT,
+                ptr::addr_of!(request),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                0,
+            )
+        };
+
+        let Ok(count) = usize::try_from(count) else {
+            return Err(io::Error::last_os_error());
+        };
+
+        ids.extend_from_slice(&buffer[..count]);
+
+        if count < buffer.len() {
+            return Ok(ids);
+        }
+    }
+}
+
+/// Describes a mount with `statmount`, returning it along with its reusable mount ID, and
+/// whether the kernel was able to report its source and file system options.
+fn statmount(mount_id: u64) -> io::Result<(MountInfo, u32, bool)> {
+    let request = MntIdReq {
+        size: MNT_ID_REQ_SIZE_VER0,
+        spare: 0,
+        mnt_id: mount_id,
+        param: STATMOUNT_SB_BASIC
+            | STATMOUNT_MNT_BASIC
+            | STATMOUNT_PROPAGATE_FROM
+            | STATMOUNT_MNT_ROOT
+            | STATMOUNT_MNT_POINT
+            | STATMOUNT_FS_TYPE
+            | STATMOUNT_MNT_OPTS
+            | STATMOUNT_SB_SOURCE,
+    };
+
+    // Allocated as `u64`s to satisfy the alignment of `struct statmount`.
+    let mut buffer = vec![0u64; 512];
+
+    loop {
+        let result = unsafe {
+            syscall(
+                SYS_STATMOUNT,
+                ptr::addr_of!(request),
+                buffer.as_mut_ptr(),
+                buffer.len() * 8,
+                0,
+            )
+        };
+
+        if result == 0 {
+            break;
+        }
+
+        let why = io::Error::last_os_error();
+        if why.raw_os_error() != Some(EOVERFLOW) {
+            return Err(why);
+        }
+
+        buffer.resize(buffer.len() * 2, 0);
+    }
+
+    let stat = unsafe { &*buffer.as_ptr().cast::<StatMount>() };
+    let bytes = unsafe { slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), buffer.len() * 8) };
+
+    let string = |flag: u64, offset: u32| -> &OsStr {
+        if stat.mask & flag == 0 {
+            return OsStr::new("");
+        }
+
+        bytes
+            .get(STATMOUNT_STRINGS + offset as usize..)
+            .and_then(|bytes| CStr::from_bytes_until_nul(bytes).ok())
+            .map_or(OsStr::new(""), |s| OsStr::from_bytes(s.to_bytes()))
+    };
+
+    let mut super_options = superblock_options(stat.sb_flags);
+    let fs_options = string(STATMOUNT_MNT_OPTS, stat.mnt_opts).to_string_lossy();
+    if !fs_options.is_empty() {
+        super_options.push(',');
+        super_options.push_str(&fs_options);
+    }
+
+    let info = MountInfo {
+        mount_id: stat.mnt_id,
+        parent_id: stat.mnt_parent_id,
+        major: stat.sb_dev_major,
+        minor: stat.sb_dev_minor,
+        root: PathBuf::from(string(STATMOUNT_MNT_ROOT, stat.mnt_root)),
+        mount_point: PathBuf::from(string(STATMOUNT_MNT_POINT, stat.mnt_point)),
+        mount_options: mount_options(MountAttr::from_bits_retain(stat.mnt_attr)),
+        optional_fields: optional_fields(stat),
+        fstype: string(STATMOUNT_FS_TYPE, stat.fs_type)
+            .to_string_lossy()
+            .into_owned(),
+        source: string(STATMOUNT_SB_SOURCE, stat.sb_source)
+            .to_string_lossy()
+            .into_owned(),
+        super_options,
+    };
+
+    let complete = stat.mask & (STATMOUNT_MNT_OPTS | STATMOUNT_SB_SOURCE)
+        == STATMOUNT_MNT_OPTS | STATMOUNT_SB_SOURCE;
+
+    Ok((info, stat.mnt_id_old, complete))
+}
+
+/// Formats mount attributes in the same way as the per-mount options of mountinfo.
+fn mount_options(attr: MountAttr) -> String {
+    let mut options = vec![if attr.contains(MountAttr::RDONLY) {
+        "ro"
+    } else {
+        "rw"
+    }];
+
+    let names = [
+        (MountAttr::NOSUID, "nosuid"),
+        (MountAttr::NODEV, "nodev"),
+        (MountAttr::NOEXEC, "noexec"),
+        (MountAttr::NOATIME, "noatime"),
+        (MountAttr::NODIRATIME, "nodiratime"),
+    ];
+
+    options.extend(
+        names
+            .iter()
+            .filter(|(a, _)| attr.contains(*a))
+            .map(|(_, n)| n),
+    );
+
+    if !attr.intersects(MountAttr::NOATIME | MountAttr::STRICTATIME) {
+        options.push("relatime");
+    }
+
+    if attr.contains(MountAttr::NOSYMFOLLOW) {
+        options.push("nosymfollow");
+    }
+
+    if attr.contains(MountAttr::IDMAP) {
+        options.push("idmapped");
+    }
+
+    options.join(",")
+}
+
+/// Formats superblock flags in the same way as the leading super options of mountinfo.
+fn superblock_options(sb_flags: u32) -> String {
+    let mut options = vec![if sb_flags & SB_RDONLY == 0 {
+        "rw"
+    } else {
+        "ro"
+    }];
+
+    let names = [
+        (SB_SYNCHRONOUS, "sync"),
+        (SB_DIRSYNC, "dirsync"),
+        (SB_MANDLOCK, "mand"),
+        (SB_LAZYTIME, "lazytime"),
+    ];
+
+    options.extend(
+        names
+            .iter()
+            .filter(|(f, _)| sb_flags & f != 0)
+            .map(|(_, n)| n),
+    );
+    options.join(",")
+}
+
+/// Formats propagation state in the same way as the optional fields of mountinfo.
+// `c_ulong` is only 32 bits wide on 32-bit targets.
+#[allow(clippy::useless_conversion)]
+fn optional_fields(stat: &StatMount) -> Vec<String> {
+    let propagation = stat.mnt_propagation;
+    let mut fields = Vec::new();
+
+    if propagation & u64::from(MS_SHARED) != 0 {
+        fields.push(format!("shared:{}", stat.mnt_peer_group));
+    }
+
+    if propagation & u64::from(MS_SLAVE) != 0 {
+        fields.push(format!("master:{}", stat.mnt_master));
+
+        if stat.mask & STATMOUNT_PROPAGATE_FROM != 0
+            && stat.propagate_from != 0
+            && stat.propagate_from != stat.mnt_master
+        {
+            fields.push(format!("propagate_from:{}", stat.propagate_from));
+        }
+    }
+
+    if propagation & u64::from(MS_UNBINDABLE) != 0 {
+        fields.push("unbindable".to_owned());
+    }
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Mount, Unmount, UnmountFlags};
+    use std::cell::Cell;
+
+    thread_local! {
+        /// Lists mounts from mountinfo on this thread, as though `listmount` were blocked.
+        pub(super) static FORCE_MOUNTINFO: Cell<bool> = const { Cell::new(false) };
+    }
+
+    #[test]
+    #[ignore = "requires CAP_SYS_ADMIN"]
+    fn mount_ids_match_table() {
+        for force in [false, true] {
+            FORCE_MOUNTINFO.with(|forced| forced.set(force));
+
+            let target = std::env::temp_dir().join(format!("sys-mount-id-{}", std::process::id()));
+            fs::create_dir_all(&target).unwrap();
+
+            let mount = Mount::builder()
+                .fstype("tmpfs")
+                .mount("tmpfs", &target)
+                .unwrap();
+
+            let table = MountTable::list().unwrap();
+            let found = mount
+                .mount_id()
+                .and_then(|id| table.get(id))
+                .map(|info| info.mount_point.clone());
+
+            mount.unmount(UnmountFlags::DETACH).unwrap();
+            fs::remove_dir(&target).unwrap();
+
+            assert_eq!(table.unique_ids, !force);
+            assert_eq!(found.as_deref(), Some(target.as_path()));
+        }
+    }
+}
@@ -0,0 +1,352 @@
+// Copyright 2018-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::{MountInfo, MountTable};
+use std::{collections::HashMap, fmt, io, iter::FromIterator, ops::Deref, path::Path};
+
+/// The mounts of a mount table, linked to one another by their parent IDs.
+///
+/// ```rust
+/// use sys_mount::{MountInfo, MountTree};
+///
+/// let mountinfo = "\
+/// 20 1 8:1 / / rw - ext4 /dev/sda1 rw
+/// 21 20 8:2 / /target rw - ext4 /dev/sda2 rw
+/// 22 21 8:3 / /target/boot rw - vfat /dev/sda3 rw
+/// 23 21 0:5 / /target/proc rw - proc proc rw
+/// 24 22 8:4 / /target/boot rw - vfat /dev/sda4 rw";
+///
+/// let tree = mountinfo
+///     .lines()
+///     .map(str::parse::<MountInfo>)
+///     .collect::<std::io::Result<MountTree>>()
+///     .unwrap();
+///
+/// // `/dev/sda4` was mounted over `/dev/sda3`, hiding it.
+/// let boot = tree.find("/target/boot/efi").unwrap();
+/// assert_eq!(boot.source, "/dev/sda4");
+/// assert!(tree.get(22).unwrap().is_shadowed());
+///
+/// let ancestors = boot.ancestors().map(|node| node.mount_id).collect::<Vec<_>>();
+/// assert_eq!(ancestors, [22, 21, 20]);
+///
+/// // Everything beneath `/target`, in the order that it may be unmounted.
+/// let order = tree.under("/target").map(|node| node.mount_id).collect::<Vec<_>>();
+/// assert_eq!(order, [23, 24, 22, 21]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct MountTree {
+    mounts: Vec<MountInfo>,
+    parents: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    shadowed: Vec<bool>,
+}
+
+impl MountTree {
+    /// Builds a tree from the mounts of the current mount namespace, as listed by
+    /// [`MountTable::list`].
+    ///
+    /// # Errors
+    ///
+    /// If the mount table could not be read.
+    pub fn new() -> io::Result<Self> {
+        MountTable::list().map(MountTree::from)
+    }
+
+    /// The mounts whose parents are not in the tree, which is usually just the root mount.
+    pub fn roots(&self) -> impl Iterator<Item = MountNode<'_>> {
+        (0..self.mounts.len())
+            .filter(move |&index| self.parents[index].is_none())
+            .map(move |index| self.node(index))
+    }
+
+    /// Finds the mount with the given ID.
+    #[must_use]
+    pub fn get(&self, mount_id: u64) -> Option<MountNode<'_>> {
+        self.mounts
+            .iter()
+            .position(|info| info.mount_id == mount_id)
+            .map(|index| self.node(index))
+    }
+
+    /// Finds the visible mount which covers `path`.
+    ///
+    /// The path is compared lexically with the mount points in the tree, so it should be
+    /// absolute and free of symbolic links.
+    #[must_use]
+    pub fn find(&self, path: impl AsRef<Path>) -> Option<MountNode<'_>> {
+        let path = path.as_ref();
+        let covers = |index: &usize| path.starts_with(&self.mounts[*index].mount_point);
+
+        let mut node = self.roots().map(|node| node.index).filter(covers).last()?;
+
+        // Descend through any mount stacked on top of this one, and otherwise through the
+        // deepest mount point, preferring the most recent of those at the same point.
+        while let Some(child) = self.children[node]
+            .iter()
+            .copied()
+            .filter(covers)
+            .max_by_key(|&index| {
+                let point = &self.mounts[index].mount_point;
+                (
+                    *point == self.mounts[node].mount_point,
+                    point.components().count(),
+                )
+            })
+        {
+            node = child;
+        }
+
+        Some(self.node(node))
+    }
+
+    /// Every mount at or beneath `path`, ordered deepest-first such that each mount comes
+    /// before its parent.
+    ///
+    /// The path is compared lexically with the mount points in the tree.
+    pub fn under(&self, path: impl AsRef<Path>) -> impl Iterator<Item = MountNode<'_>> {
+        let path = path.as_ref();
+        let beneath = |index: usize| self.mounts[index].mount_point.starts_with(path);
+
+        // The highest mounts beneath the path, whose subtrees contain the rest.
+        let tops = (0..self.mounts.len())
+            .filter(|&index| beneath(index))
+            .filter(|&index| !self.parents[index].is_some_and(beneath))
+            .collect::<Vec<_>>();
+
+        let order = tops
+            .into_iter()
+            .rev()
+            .flat_map(|index| self.subtree_order(index))
+            .collect::<Vec<_>>();
+
+        order.into_iter().map(move |index| self.node(index))
+    }
+
+    /// Iterates over every mount in the tree, in the order of the mount table.
+    pub fn iter(&self) -> impl Iterator<Item = MountNode<'_>> {
+        (0..self.mounts.len()).map(move |index| self.node(index))
+    }
+
+    fn node(&self, index: usize) -> MountNode<'_> {
+        MountNode { tree: self, index }
+    }
+
+    /// Orders the subtree of `index` such that children precede their parents, and later
+    /// mounts precede earlier siblings.
+    fn subtree_order(&self, index: usize) -> Vec<usize> {
+        let mut order = Vec::new();
+        let mut stack = vec![index];
+
+        while let Some(index) = stack.pop() {
+            order.push(index);
+            stack.extend(self.children[index].iter().rev().copied());
+        }
+
+        order.reverse();
+        order
+    }
+}
+
+impl From<MountTable> for MountTree {
+    fn from(table: MountTable) -> Self {
+        table.into_iter().collect()
+    }
+}
+
+impl FromIterator<MountInfo> for MountTree {
+    fn from_iter<I: IntoIterator<Item = MountInfo>>(iter: I) -> Self {
+        let mounts = iter.into_iter().collect::<Vec<_>>();
+
+        let indexes = mounts
+            .iter()
+            .enumerate()
+            .map(|(index, info)| (info.mount_id, index))
+            .collect::<HashMap<_, _>>();
+
+        let parents = mounts
+            .iter()
+            .map(|info| {
+                indexes
+                    .get(&info.parent_id)
+                    .copied()
+                    .filter(|_| info.parent_id != info.mount_id)
+            })
+            .collect::<Vec<_>>();
+
+        let mut children = vec![Vec::new(); mounts.len()];
+        for (index, parent) in parents.iter().enumerate() {
+            if let Some(parent) = *parent {
+                children[parent].push(index);
+            }
+        }
+
+        // A mount is hidden when another is mounted on top of it, or beside it by a later
+        // mount at the same point of the same parent.
+        let shadowed = (0..mounts.len())
+            .map(|index| {
+                let point = &mounts[index].mount_point;
+                let siblings = parents[index].map_or(&[][..], |parent| &children[parent][..]);
+
+                children[index]
+                    .iter()
+                    .any(|&child| mounts[child].mount_point == *point)
+                    || siblings
+                        .iter()
+                        .any(|&sibling| sibling > index && mounts[sibling].mount_point == *point)
+            })
+            .collect();
+
+        MountTree {
+            mounts,
+            parents,
+            children,
+            shadowed,
+        }
+    }
+}
+
+/// A mount within a [`MountTree`], which dereferences to its [`MountInfo`].
+#[derive(Clone, Copy)]
+#[allow(clippy::module_name_repetitions)]
+pub struct MountNode<'a> {
+    tree: &'a MountTree,
+    index: usize,
+}
+
+impl<'a> MountNode<'a> {
+    /// The description of this mount from the mount table.
+    #[must_use]
+    pub fn info(&self) -> &'a MountInfo {
+        &self.tree.mounts[self.index]
+    }
+
+    /// The mount which this mount is mounted on, unless it is a root of the tree.
+    #[must_use]
+    pub fn parent(&self) -> Option<MountNode<'a>> {
+        self.tree.parents[self.index].map(|index| self.tree.node(index))
+    }
+
+    /// The mounts which are mounted directly on this mount, in the order of the mount table.
+    pub fn children(&self) -> impl Iterator<Item = MountNode<'a>> {
+        let tree = self.tree;
+        tree.children[self.index]
+            .iter()
+            .map(move |&index| tree.node(index))
+    }
+
+    /// The parent of this mount, followed by its parent, up to a root of the tree.
+    pub fn ancestors(&self) -> impl Iterator<Item = MountNode<'a>> {
+        std::iter::successors(self.parent(), MountNode::parent)
+    }
+
+    /// This mount and every mount beneath it, ordered deepest-first such that each mount
+    /// comes before its parent, and this mount comes last.
+    pub fn subtree(&self) -> impl Iterator<Item = MountNode<'a>> {
+        let tree = self.tree;
+        tree.subtree_order(self.index)
+            .into_iter()
+            .map(move |index| tree.node(index))
+    }
+
+    /// Whether this mount is hidden by a later mount at the same mount point.
+    #[must_use]
+    pub fn is_shadowed(&self) -> bool {
+        self.tree.shadowed[self.index]
+    }
+}
+
+impl Deref for MountNode<'_> {
+    type Target = MountInfo;
+
+    fn deref(&self) -> &MountInfo {
+        self.info()
+    }
+}
+
+impl fmt::Debug for MountNode<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("MountNode")
+            .field("info", self.info())
+            .field("shadowed", &self.is_shadowed())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(mountinfo: &str) -> MountTree {
+        mountinfo
+            .lines()
+            .map(str::parse::<MountInfo>)
+            .collect::<io::Result<MountTree>>()
+            .unwrap()
+    }
+
+    fn ids<'a>(nodes: impl Iterator<Item = MountNode<'a>>) -> Vec<u64> {
+        nodes.map(|node| node.mount_id).collect()
+    }
+
+    #[test]
+    fn shadowed_mounts() {
+        let tree = tree(
+            "\
+1 0 8:1 / / rw - ext4 /dev/sda1 rw
+2 1 0:20 / /tmp rw - tmpfs tmpfs rw
+3 1 0:21 / /tmp rw - tmpfs tmpfs rw
+4 3 0:22 / /tmp rw - tmpfs tmpfs rw
+5 1 0:23 / /run rw - tmpfs tmpfs rw",
+        );
+
+        // The second mount beside the first hides it, and the third on top hides the second.
+        let shadowed = tree.iter().filter(MountNode::is_shadowed);
+        assert_eq!(ids(shadowed), [2, 3]);
+        assert_eq!(tree.find("/tmp/file").unwrap().mount_id, 4);
+        assert_eq!(tree.find("/run").unwrap().mount_id, 5);
+        assert_eq!(tree.find("/home").unwrap().mount_id, 1);
+    }
+
+    #[test]
+    fn under_path_without_mount() {
+        let tree = tree(
+            "\
+1 0 8:1 / / rw - ext4 /dev/sda1 rw
+2 1 8:2 / /target rw - ext4 /dev/sda2 rw
+3 2 8:3 / /target/var/lib rw - ext4 /dev/sda3 rw
+4 2 8:4 / /target/var/log rw - ext4 /dev/sda4 rw
+5 3 0:20 / /target/var/lib/cache rw - tmpfs tmpfs rw
+6 2 8:5 / /target/variable rw - ext4 /dev/sda5 rw",
+        );
+
+        // Only the mounts beneath `/target/var` are listed, and not `/target` itself.
+        assert_eq!(ids(tree.under("/target/var")), [4, 5, 3]);
+        assert_eq!(ids(tree.under("/target/var/lib/cache/data")), []);
+    }
+
+    #[test]
+    fn subtree_order_by_depth() {
+        let tree = tree(
+            "\
+1 0 8:1 / / rw - ext4 /dev/sda1 rw
+2 1 8:2 / /a rw - ext4 /dev/sda2 rw
+3 2 8:3 / /a/b rw - ext4 /dev/sda3 rw
+4 3 8:4 / /a/b/c rw - ext4 /dev/sda4 rw
+5 1 8:5 / /d rw - ext4 /dev/sda5 rw
+6 2 8:6 / /a/e rw - ext4 /dev/sda6 rw",
+        );
+
+        let order = ids(tree.get(1).unwrap().subtree());
+        assert_eq!(order, [5, 6, 4, 3, 2, 1]);
+
+        // Every mount comes after each mount beneath it.
+        for node in tree.iter() {
+            let position = |id| order.iter().position(|&other| other == id).unwrap();
+            for ancestor in node.ancestors() {
+                assert!(position(node.mount_id) < position(ancestor.mount_id));
+            }
+        }
+    }
+}
@@ -1,9 +1,19 @@
 // Copyright 2018-2022 System76 <info@system76.com>
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use crate::UnmountFlags;
-use libc::{c_char, umount2};
-use std::{ffi::CString, io, ops::Deref, os::unix::ffi::OsStrExt, path::Path, ptr};
+use crate::{MountInfo, MountNode, MountTable, MountTree, UnmountFlags};
+use libc::{c_char, umount2, EBUSY, EINVAL};
+use std::{
+    ffi::CString,
+    fs, io,
+    ops::Deref,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileTypeExt, MetadataExt},
+    },
+    path::{Path, PathBuf},
+    ptr,
+};
 
 /// Unmount trait which enables any type that implements it to be upgraded into an `UnmountDrop`.
 pub trait Unmount {
@@ -86,6 +96,246 @@ pub fn unmount<P: AsRef<Path>>(path: P, flags: UnmountFlags) -> io::Result<()> {
     unsafe { unmount_(mount_ptr, flags) }
 }
 
+/// Unmounts every mount at or beneath `path`, deepest-first, much like `umount -R`.
+///
+/// Mounts are found from the mount table, so `path` need not be a mount point itself. A
+/// failure to unmount one mount does not stop the rest from being attempted. If nothing is
+/// mounted at or beneath `path`, this succeeds without doing anything.
+///
+/// # Errors
+///
+/// - If the mount table could not be read
+/// - If any mount could not be unmounted, in which case the error wraps an
+///   [`UnmountRecursiveError`] describing each failure
+///
+/// # Example
+///
+/// ```rust,no_run
+/// extern crate sys_mount;
+///
+/// use sys_mount::{unmount_recursive, UnmountFlags, UnmountRecursiveError};
+///
+/// fn main() {
+///     // Tear down a chroot, along with its `/proc`, `/sys`, and `/dev` mounts.
+///     if let Err(why) = unmount_recursive("/target", UnmountFlags::empty()) {
+///         let failures = why
+///             .get_ref()
+///             .and_then(|why| why.downcast_ref::<UnmountRecursiveError>());
+///
+///         for failure in failures.map_or(&[][..], UnmountRecursiveError::failures) {
+///             eprintln!("{}", failure);
+///         }
+///     }
+/// }
+/// ```
+pub fn unmount_recursive<P: AsRef<Path>>(path: P, flags: UnmountFlags) -> io::Result<()> {
+    unmount_tree(path.as_ref(), flags, false)
+}
+
+/// Unmounts every mount at or beneath `path` like [`unmount_recursive`], but lazily detaches
+/// any mount which is busy, as if it were unmounted with `UnmountFlags::DETACH`.
+///
+/// # Errors
+///
+/// - If the mount table could not be read
+/// - If any mount could not be unmounted or detached, in which case the error wraps an
+///   [`UnmountRecursiveError`] describing each failure
+pub fn unmount_recursive_or_detach<P: AsRef<Path>>(path: P, flags: UnmountFlags) -> io::Result<()> {
+    unmount_tree(path.as_ref(), flags, true)
+}
+
+fn unmount_tree(path: &Path, flags: UnmountFlags, detach_busy: bool) -> io::Result<()> {
+    // Mount points are compared lexically, so resolve any symbolic links in the path.
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let tree = MountTree::new()?;
+    unmount_nodes(&path, tree.under(&path), flags, detach_busy)
+}
+
+/// Unmounts each of the `nodes` in order, collecting the failures into an
+/// [`UnmountRecursiveError`] for `path`.
+fn unmount_nodes<'a>(
+    path: &Path,
+    nodes: impl Iterator<Item = MountNode<'a>>,
+    flags: UnmountFlags,
+    detach_busy: bool,
+) -> io::Result<()> {
+    let mut failures = Vec::new();
+
+    for node in nodes {
+        let mut result = unmount(&node.mount_point, flags);
+
+        if detach_busy {
+            if let Err(ref why) = result {
+                if why.raw_os_error() == Some(EBUSY) {
+                    result = unmount(&node.mount_point, flags | UnmountFlags::DETACH);
+                }
+            }
+        }
+
+        let Err(why) = result else {
+            continue;
+        };
+
+        // The mount may have already gone, taken down with a lazily detached parent or by
+        // propagation from the unmount of one of its peers.
+        if why.raw_os_error() == Some(EINVAL) && !is_mounted(node.mount_id)? {
+            continue;
+        }
+
+        failures.push(UnmountFailure {
+            mount_point: node.mount_point.clone(),
+            mount_id: node.mount_id,
+            source: why,
+        });
+    }
+
+    match failures.first() {
+        None => Ok(()),
+        Some(first) => Err(io::Error::new(
+            first.source.kind(),
+            UnmountRecursiveError {
+                path: path.to_owned(),
+                failures,
+            },
+        )),
+    }
+}
+
+/// Unmounts every mount of the block `device`, including bind mounts of it, along with
+/// anything mounted beneath them.
+///
+/// Mounts are matched by the device number of their file system rather than by their source,
+/// so mounts made through another path to the device, such as a `/dev/disk/by-uuid` symlink,
+/// are found as well.
+///
+/// # Errors
+///
+/// - If `device` is not a block device
+/// - If the mount table could not be read
+/// - If any mount could not be unmounted, in which case the error wraps an
+///   [`UnmountRecursiveError`] describing each failure
+///
+/// # Example
+///
+/// ```rust,no_run
+/// extern crate sys_mount;
+///
+/// use sys_mount::{unmount_source, UnmountFlags};
+///
+/// fn main() {
+///     // Release the partition before it is resized.
+///     let result = unmount_source("/dev/sda2", UnmountFlags::empty());
+/// }
+/// ```
+pub fn unmount_source<P: AsRef<Path>>(device: P, flags: UnmountFlags) -> io::Result<()> {
+    unmount_device(device.as_ref(), flags, false)
+}
+
+/// Unmounts every mount of the block `device` like [`unmount_source`], after first unmounting
+/// and detaching any loop devices whose backing files reside on it.
+///
+/// # Errors
+///
+/// - If a loop device backed by the device could not be unmounted or detached
+/// - Or for any of the reasons that [`unmount_source`] fails
+#[cfg(feature = "loop")]
+pub fn unmount_source_and_loops<P: AsRef<Path>>(device: P, flags: UnmountFlags) -> io::Result<()> {
+    unmount_device(device.as_ref(), flags, true)
+}
+
+fn unmount_device(device: &Path, flags: UnmountFlags, detach_loops: bool) -> io::Result<()> {
+    let device = fs::canonicalize(device)?;
+    let metadata = fs::metadata(&device)?;
+
+    if !metadata.file_type().is_block_device() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a block device", device.display()),
+        ));
+    }
+
+    let rdev = metadata.rdev();
+    let number = (libc::major(rdev), libc::minor(rdev));
+
+    // File systems such as btrfs present an anonymous device number in place of their block
+    // device, so mounts whose source resolves to the device are matched as well.
+    let matches = |info: &MountInfo| {
+        (info.major, info.minor) == number
+            || (info.source.starts_with('/')
+                && fs::canonicalize(&info.source).is_ok_and(|source| source == device))
+    };
+
+    if detach_loops {
+        #[cfg(feature = "loop")]
+        {
+            let tree = MountTree::new()?;
+            let mut numbers = tree
+                .iter()
+                .filter(|node| matches(node))
+                .map(|node| (node.major, node.minor))
+                .collect::<Vec<_>>();
+            numbers.push(number);
+
+            for loopback in loopbacks_backed_by(&numbers)? {
+                unmount_device(&loopback, flags, true)?;
+                detach_loopback(&loopback)?;
+            }
+        }
+    }
+
+    let tree = MountTree::new()?;
+
+    // The highest matching mounts, whose subtrees contain every other mount to unmount.
+    let tops = tree
+        .iter()
+        .filter(|node| matches(node))
+        .filter(|node| !node.ancestors().any(|ancestor| matches(&ancestor)))
+        .collect::<Vec<_>>();
+
+    let nodes = tops.into_iter().rev().flat_map(|node| node.subtree());
+    unmount_nodes(&device, nodes, flags, false)
+}
+
+fn is_mounted(mount_id: u64) -> io::Result<bool> {
+    MountTable::list().map(|table| table.get(mount_id).is_some())
+}
+
+/// A mount which could not be unmounted by [`unmount_recursive`] or [`unmount_source`].
+#[derive(Debug, Error)]
+#[error("failed to unmount {}: {source}", .mount_point.display())]
+pub struct UnmountFailure {
+    /// Where the mount was mounted.
+    pub mount_point: PathBuf,
+    /// The ID of the mount, as listed by [`MountTable::list`].
+    pub mount_id: u64,
+    /// Why the mount could not be unmounted.
+    #[source]
+    pub source: io::Error,
+}
+
+/// The mounts which could not be unmounted by [`unmount_recursive`] or [`unmount_source`].
+#[derive(Debug, Error)]
+#[error("failed to unmount {} of the mounts of {}", .failures.len(), .path.display())]
+pub struct UnmountRecursiveError {
+    path: PathBuf,
+    failures: Vec<UnmountFailure>,
+}
+
+impl UnmountRecursiveError {
+    /// The path beneath which mounts were unmounted, or the device whose mounts were
+    /// unmounted.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Each mount which could not be unmounted, in the order they were attempted.
+    #[must_use]
+    pub fn failures(&self) -> &[UnmountFailure] {
+        &self.failures
+    }
+}
+
 #[inline]
 pub(crate) unsafe fn unmount_(mount_ptr: *const c_char, flags: UnmountFlags) -> io::Result<()> {
     match umount2(mount_ptr, flags.bits()) {
@@ -93,3 +343,74 @@ pub(crate) unsafe fn unmount_(mount_ptr: *const c_char, flags: UnmountFlags) ->
         _err => Err(io::Error::last_os_error()),
     }
 }
+
+#[cfg(feature = "loop")]
+const LOOP_GET_STATUS64: libc::Ioctl = 0x4C05;
+
+/// The `loop_info64` structure of `linux/loop.h`.
+#[cfg(feature = "loop")]
+#[repr(C)]
+#[allow(dead_code)]
+struct LoopInfo {
+    device: u64,
+    inode: u64,
+    rdevice: u64,
+    offset: u64,
+    size_limit: u64,
+    number: u32,
+    encrypt_type: u32,
+    encrypt_key_size: u32,
+    flags: u32,
+    file_name: [u8; 64],
+    crypt_name: [u8; 64],
+    encrypt_key: [u8; 32],
+    init: [u64; 2],
+}
+
+/// Finds the attached loop devices whose backing files reside on a file system with one of
+/// the given device `numbers`.
+#[cfg(feature = "loop")]
+fn loopbacks_backed_by(numbers: &[(u32, u32)]) -> io::Result<Vec<PathBuf>> {
+    use std::{mem::MaybeUninit, os::fd::AsRawFd};
+
+    let mut loopbacks = Vec::new();
+
+    for entry in fs::read_dir("/sys/block")? {
+        let name = entry?.file_name();
+
+        // Only attached loop devices have a `loop` directory.
+        if !name.as_bytes().starts_with(b"loop")
+            || !Path::new("/sys/block").join(&name).join("loop").exists()
+        {
+            continue;
+        }
+
+        let path = Path::new("/dev").join(&name);
+        let Ok(file) = fs::File::open(&path) else {
+            continue;
+        };
+
+        let mut info = MaybeUninit::<LoopInfo>::zeroed();
+        let result = unsafe { libc::ioctl(file.as_raw_fd(), LOOP_GET_STATUS64, info.as_mut_ptr()) };
+
+        if result != 0 {
+            continue;
+        }
+
+        let device = unsafe { info.assume_init() }.device;
+        if numbers.contains(&(libc::major(device), libc::minor(device))) {
+            loopbacks.push(path);
+        }
+    }
+
+    Ok(loopbacks)
+}
+
+#[cfg(feature = "loop")]
+fn detach_loopback(path: &Path) -> io::Result<()> {
+    match loopdev::LoopDevice::open(path)?.detach() {
+        // Loop devices attached with autoclear detach themselves once they are unmounted.
+        Err(why) if why.raw_os_error() == Some(libc::ENXIO) => Ok(()),
+        result => result,
+    }
+}
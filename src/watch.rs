@@ -0,0 +1,555 @@
+// Copyright 2018-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::table::{describe, unique_ids};
+use crate::{MountInfo, MountTable};
+use libc::{
+    c_int, c_uint, c_void, pollfd, AT_FDCWD, EAGAIN, FAN_CLASS_NOTIF, FAN_CLOEXEC, FAN_MARK_ADD,
+    FAN_NONBLOCK, O_RDONLY, POLLERR, POLLIN, POLLPRI,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    time::Duration,
+};
+
+// Not yet exported by `libc`.
+const FAN_REPORT_MNT: c_uint = 0x0000_4000;
+const FAN_MARK_MNTNS: c_uint = 0x0000_0110;
+const FAN_MNT_ATTACH: u64 = 0x0100_0000;
+const FAN_MNT_DETACH: u64 = 0x0200_0000;
+const FAN_EVENT_INFO_TYPE_MNT: u8 = 7;
+
+/// Length of `struct fanotify_event_metadata`, which precedes the info records of an event.
+const FAN_EVENT_METADATA_LEN: usize = 24;
+
+/// How a [`MountWatcher`] learns of changes to the mount table.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum WatchBackend {
+    /// Use fanotify where the kernel supports it, and otherwise poll mountinfo.
+    #[default]
+    Auto,
+    /// Receive mount attach and detach events, with their mount IDs, through fanotify on the
+    /// mount namespace. Requires Linux 6.14 and `CAP_SYS_ADMIN`.
+    Fanotify,
+    /// Poll `/proc/self/mountinfo`, and compare listings of the mount table.
+    Poll,
+}
+
+/// A change to the mount table, as reported by a [`MountWatcher`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum MountEvent {
+    /// A mount was attached.
+    Mounted(MountInfo),
+    /// A mount was detached.
+    Unmounted(MountInfo),
+    /// The options or propagation of a mount changed, from the first to the second.
+    Remounted(MountInfo, MountInfo),
+}
+
+/// Watches the mount table of this process for changes.
+///
+/// By default, mounts which are attached and detached are reported by fanotify, which names
+/// each mount by its ID, so every attach and detach is seen in order, even when the mount
+/// table looks the same before and after. Only a mount which is detached again before it can
+/// be described is omitted. On kernels without fanotify mount events, without
+/// `CAP_SYS_ADMIN`, or where `listmount` is blocked, the watcher falls back to comparing
+/// listings of the mount table, so a mount which comes and goes between two listings is
+/// never reported.
+///
+/// Either way, `/proc/self/mountinfo` is polled for `POLLPRI`, which the kernel raises
+/// whenever a mount is attached, detached, or remounted in the mount namespace. Remounts are
+/// always found by comparing listings, as fanotify does not report them.
+///
+/// To integrate with an event loop, register the watcher's file descriptor for `POLLPRI` and
+/// call [`MountWatcher::wait`] with a zero timeout whenever it becomes ready.
+///
+/// ```no_run
+/// use sys_mount::*;
+///
+/// fn main() -> std::io::Result<()> {
+///     for event in MountWatcher::new()? {
+///         match event? {
+///             MountEvent::Mounted(info) => println!("mounted {}", info.mount_point.display()),
+///             MountEvent::Unmounted(info) => println!("unmounted {}", info.mount_point.display()),
+///             MountEvent::Remounted(_, info) => println!("remounted {}", info.mount_point.display()),
+///         }
+///     }
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct MountWatcher {
+    mountinfo: File,
+    fanotify: Option<OwnedFd>,
+    table: MountTable,
+    pending: VecDeque<MountEvent>,
+}
+
+impl MountWatcher {
+    /// Lists the current mount table, and begins watching it for changes with the best
+    /// backend that the kernel supports.
+    ///
+    /// # Errors
+    ///
+    /// If `/proc/self/mountinfo` could not be opened, or the mount table could not be listed.
+    pub fn new() -> io::Result<Self> {
+        Self::with_backend(WatchBackend::Auto)
+    }
+
+    /// Lists the current mount table, and begins watching it for changes with `backend`.
+    ///
+    /// # Errors
+    ///
+    /// - If `/proc/self/mountinfo` could not be opened, or the mount table could not be listed
+    /// - If fanotify was requested, but the kernel does not support mount events, the process
+    ///   lacks `CAP_SYS_ADMIN`, or `listmount` is unavailable, such as under a seccomp filter
+    pub fn with_backend(backend: WatchBackend) -> io::Result<Self> {
+        // Begin watching first, so that no change after the listing can be missed.
+        let mountinfo = File::open("/proc/self/mountinfo")?;
+
+        // Fanotify names mounts by their unique IDs, which are only listed by `listmount`.
+        let fanotify = match backend {
+            WatchBackend::Auto if unique_ids() => fanotify_mount_events().ok(),
+            WatchBackend::Fanotify if unique_ids() => Some(fanotify_mount_events()?),
+            WatchBackend::Fanotify => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "fanotify mount events require listmount to describe mounts",
+                ))
+            }
+            WatchBackend::Auto | WatchBackend::Poll => None,
+        };
+
+        Ok(MountWatcher {
+            mountinfo,
+            fanotify,
+            table: MountTable::list()?,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// The backend which the watcher is using, which is never `Auto`.
+    #[must_use]
+    pub fn backend(&self) -> WatchBackend {
+        if self.fanotify.is_some() {
+            WatchBackend::Fanotify
+        } else {
+            WatchBackend::Poll
+        }
+    }
+
+    /// The mount table as of the most recent change.
+    #[must_use]
+    pub fn table(&self) -> &MountTable {
+        &self.table
+    }
+
+    /// Waits for the mount table to change, returning the events which describe the change.
+    ///
+    /// Blocks until a change occurs, or until the `timeout` elapses, in which case no events
+    /// are returned. A timeout of zero checks for a change without blocking. Events which were
+    /// not yet taken by the `Iterator` implementation are returned first, without waiting.
+    ///
+    /// # Errors
+    ///
+    /// If polling failed, or the mount table could not be listed.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> io::Result<Vec<MountEvent>> {
+        if !self.pending.is_empty() {
+            return Ok(self.pending.drain(..).collect());
+        }
+
+        let (notified, remounted) = self.poll(timeout)?;
+        let mut events = Vec::new();
+
+        if notified {
+            self.read_fanotify(&mut events)?;
+        }
+
+        if remounted {
+            let table = MountTable::list()?;
+
+            if self.fanotify.is_some() {
+                self.update_remounted(&table, &mut events);
+            } else {
+                events = diff(&self.table, &table);
+                self.table = table;
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Polls for fanotify events, and for a change to mountinfo, returning which are ready.
+    fn poll(&self, timeout: Option<Duration>) -> io::Result<(bool, bool)> {
+        let timeout = timeout.map_or(-1, |timeout| {
+            c_int::try_from(timeout.as_millis()).unwrap_or(c_int::MAX)
+        });
+
+        let mut fds = [
+            pollfd {
+                fd: self.mountinfo.as_raw_fd(),
+                events: POLLPRI,
+                revents: 0,
+            },
+            pollfd {
+                fd: self.fanotify.as_ref().map_or(-1, AsRawFd::as_raw_fd),
+                events: POLLIN,
+                revents: 0,
+            },
+        ];
+
+        loop {
+            match unsafe { libc::poll(fds.as_mut_ptr(), 2, timeout) } {
+                -1 => {
+                    let why = io::Error::last_os_error();
+                    if why.kind() != io::ErrorKind::Interrupted {
+                        return Err(why);
+                    }
+                }
+                0 => return Ok((false, false)),
+                _ => {
+                    return Ok((
+                        fds[1].revents & POLLIN != 0,
+                        fds[0].revents & (POLLPRI | POLLERR) != 0,
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Reads every queued fanotify event, applying each to the table.
+    fn read_fanotify(&mut self, events: &mut Vec<MountEvent>) -> io::Result<()> {
+        let Some(ref fanotify) = self.fanotify else {
+            return Ok(());
+        };
+
+        // Allocated as `u64`s to satisfy the alignment of the event records.
+        let mut buffer = [0u64; 512];
+        let mut changes = Vec::new();
+
+        loop {
+            let read = unsafe {
+                libc::read(
+                    fanotify.as_raw_fd(),
+                    buffer.as_mut_ptr().cast::<c_void>(),
+                    buffer.len() * 8,
+                )
+            };
+
+            let Ok(read) = usize::try_from(read) else {
+                let why = io::Error::last_os_error();
+                match why.raw_os_error() {
+                    Some(EAGAIN) => break,
+                    _ if why.kind() == io::ErrorKind::Interrupted => continue,
+                    _ => return Err(why),
+                }
+            };
+
+            if read == 0 {
+                break;
+            }
+
+            let bytes = unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), read) };
+            changes.extend(parse_fanotify(bytes));
+        }
+
+        for (mask, mount_id) in changes {
+            if mask & FAN_MNT_DETACH != 0 {
+                let mounts = self.table.mounts_mut();
+                if let Some(index) = mounts.iter().position(|info| info.mount_id == mount_id) {
+                    events.push(MountEvent::Unmounted(mounts.remove(index)));
+                }
+            }
+
+            if mask & FAN_MNT_ATTACH != 0 {
+                match describe(mount_id) {
+                    Ok(info) => {
+                        self.table.mounts_mut().push(info.clone());
+                        events.push(MountEvent::Mounted(info));
+                    }
+                    // The mount was detached again before it could be described.
+                    Err(why) if why.kind() == io::ErrorKind::NotFound => (),
+                    Err(why) => return Err(why),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reports and applies changes to the options of mounts in `table` which are already
+    /// known, leaving mounts which come and go to fanotify.
+    fn update_remounted(&mut self, table: &MountTable, events: &mut Vec<MountEvent>) {
+        for new in table.iter() {
+            let mounts = self.table.mounts_mut();
+            let Some(old) = mounts
+                .iter_mut()
+                .find(|old| old.mount_id == new.mount_id && old.mount_point == new.mount_point)
+            else {
+                continue;
+            };
+
+            if old != new {
+                let old = std::mem::replace(old, new.clone());
+                events.push(MountEvent::Remounted(old, new.clone()));
+            }
+        }
+    }
+}
+
+impl Iterator for MountWatcher {
+    type Item = io::Result<MountEvent>;
+
+    /// Blocks until the next change to the mount table.
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            match self.wait(None) {
+                Ok(events) => self.pending.extend(events),
+                Err(why) => return Some(Err(why)),
+            }
+        }
+
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+/// Compares two listings of the mount table by mount ID.
+///
+/// A mount whose ID was reused by a mount elsewhere, or which was moved, is reported as
+/// unmounted and mounted again.
+pub(crate) fn diff(before: &[MountInfo], after: &[MountInfo]) -> Vec<MountEvent> {
+    let same_mount = |old: &MountInfo, new: &MountInfo| {
+        old.major == new.major
+            && old.minor == new.minor
+            && old.root == new.root
+            && old.mount_point == new.mount_point
+            && old.fstype == new.fstype
+    };
+
+    let old_mounts = index(before);
+    let new_mounts = index(after);
+
+    // Mounts are detached from the deepest first, so report them in that order.
+    let unmounted = before.iter().rev().filter(|old| {
+        !new_mounts
+            .get(&old.mount_id)
+            .is_some_and(|new| same_mount(old, new))
+    });
+
+    let mut events = unmounted
+        .cloned()
+        .map(MountEvent::Unmounted)
+        .collect::<Vec<_>>();
+
+    for new in after {
+        match old_mounts.get(&new.mount_id) {
+            Some(&old) if same_mount(old, new) => {
+                if old != new {
+                    events.push(MountEvent::Remounted(old.clone(), new.clone()));
+                }
+            }
+            _ => events.push(MountEvent::Mounted(new.clone())),
+        }
+    }
+
+    events
+}
+
+impl AsFd for MountWatcher {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.mountinfo.as_fd()
+    }
+}
+
+impl AsRawFd for MountWatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.mountinfo.as_raw_fd()
+    }
+}
+
+/// Creates a fanotify group which reports mounts attached to and detached from the mount
+/// namespace of this process.
+fn fanotify_mount_events() -> io::Result<OwnedFd> {
+    let fd = unsafe {
+        libc::fanotify_init(
+            FAN_CLASS_NOTIF | FAN_REPORT_MNT | FAN_CLOEXEC | FAN_NONBLOCK,
+            O_RDONLY as c_uint,
+        )
+    };
+
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let result = unsafe {
+        libc::fanotify_mark(
+            fd.as_raw_fd(),
+            FAN_MARK_ADD | FAN_MARK_MNTNS,
+            FAN_MNT_ATTACH | FAN_MNT_DETACH,
+            AT_FDCWD,
+            b"/proc/self/ns/mnt\0".as_ptr().cast(),
+        )
+    };
+
+    match result {
+        0 => Ok(fd),
+        _err => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Parses the mask and mount ID of each mount event in a buffer read from fanotify.
+fn parse_fanotify(bytes: &[u8]) -> Vec<(u64, u64)> {
+    let u16_at = |at: usize| {
+        bytes
+            .get(at..at + 2)
+            .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+    };
+    let u32_at = |at: usize| {
+        bytes
+            .get(at..at + 4)
+            .and_then(|b| b.try_into().ok())
+            .map(u32::from_ne_bytes)
+    };
+    let u64_at = |at: usize| {
+        bytes
+            .get(at..at + 8)
+            .and_then(|b| b.try_into().ok())
+            .map(u64::from_ne_bytes)
+    };
+
+    let mut changes = Vec::new();
+    let mut offset = 0;
+
+    // Each event is `struct fanotify_event_metadata`, followed by its info records.
+    while let (Some(event_len), Some(metadata_len), Some(mask)) =
+        (u32_at(offset), u16_at(offset + 6), u64_at(offset + 8))
+    {
+        let event_len = event_len as usize;
+        if event_len < FAN_EVENT_METADATA_LEN {
+            break;
+        }
+
+        let end = offset + event_len;
+        let mut record = offset + usize::from(metadata_len);
+
+        // Each record begins with `struct fanotify_event_info_header`.
+        while let (Some(&info_type), Some(len)) = (bytes.get(record), u16_at(record + 2)) {
+            if record >= end || len == 0 {
+                break;
+            }
+
+            // `struct fanotify_event_info_mnt` holds the mount ID after the header and padding.
+            if info_type == FAN_EVENT_INFO_TYPE_MNT && len >= 16 && record + 16 <= end {
+                if let Some(mount_id) = u64_at(record + 8) {
+                    changes.push((mask, mount_id));
+                }
+            }
+
+            record += usize::from(len);
+        }
+
+        offset = end;
+    }
+
+    changes
+}
+
+fn index(mounts: &[MountInfo]) -> HashMap<u64, &MountInfo> {
+    mounts.iter().map(|info| (info.mount_id, info)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes an event with the given info records, each as its type and body.
+    fn event(mask: u64, records: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut bytes = vec![0; FAN_EVENT_METADATA_LEN];
+
+        for (info_type, body) in records {
+            let len = u16::try_from(4 + body.len()).unwrap();
+            bytes.extend([*info_type, 0]);
+            bytes.extend(len.to_ne_bytes());
+            bytes.extend(*body);
+        }
+
+        let event_len = u32::try_from(bytes.len()).unwrap();
+        bytes[..4].copy_from_slice(&event_len.to_ne_bytes());
+        bytes[6..8].copy_from_slice(&24u16.to_ne_bytes());
+        bytes[8..16].copy_from_slice(&mask.to_ne_bytes());
+        bytes
+    }
+
+    fn mnt(mount_id: u64) -> Vec<u8> {
+        let mut body = vec![0; 4];
+        body.extend(mount_id.to_ne_bytes());
+        body
+    }
+
+    fn info(mount_id: u64, mount_point: &str, options: &str) -> MountInfo {
+        format!("{mount_id} 1 8:1 / {mount_point} {options} - ext4 /dev/sda1 rw")
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_fanotify_records() {
+        let mut bytes = event(
+            FAN_MNT_ATTACH,
+            &[(1, &[0; 12]), (FAN_EVENT_INFO_TYPE_MNT, &mnt(7))],
+        );
+        bytes.extend(event(FAN_MNT_DETACH, &[(FAN_EVENT_INFO_TYPE_MNT, &mnt(9))]));
+
+        assert_eq!(
+            parse_fanotify(&bytes),
+            [(FAN_MNT_ATTACH, 7), (FAN_MNT_DETACH, 9)]
+        );
+
+        // A record whose length is too short to hold a mount ID is skipped.
+        let short = event(
+            FAN_MNT_ATTACH,
+            &[(FAN_EVENT_INFO_TYPE_MNT, &[0; 4]), (1, &[7; 12])],
+        );
+        assert!(parse_fanotify(&short).is_empty());
+
+        // An event cut off by the end of the buffer yields nothing.
+        let whole = event(FAN_MNT_ATTACH, &[(FAN_EVENT_INFO_TYPE_MNT, &mnt(7))]);
+        assert!(parse_fanotify(&whole[..whole.len() - 4]).is_empty());
+        assert!(parse_fanotify(&whole[..10]).is_empty());
+    }
+
+    #[test]
+    fn diff_listings() {
+        let before = [
+            info(1, "/", "rw"),
+            info(2, "/home", "rw"),
+            info(3, "/srv", "rw"),
+        ];
+        let after = [
+            info(1, "/", "rw"),
+            info(2, "/home", "ro"),
+            info(3, "/mnt", "rw"),
+            info(4, "/boot", "rw"),
+        ];
+
+        assert_eq!(
+            diff(&before, &after),
+            [
+                MountEvent::Unmounted(before[2].clone()),
+                MountEvent::Remounted(before[1].clone(), after[1].clone()),
+                MountEvent::Mounted(after[2].clone()),
+                MountEvent::Mounted(after[3].clone()),
+            ]
+        );
+
+        assert!(diff(&after, &after).is_empty());
+    }
+}